@@ -0,0 +1,55 @@
+//! Hands the resolved naga version to `shader_compiler`, whose cached SPIR-V must not outlive the compiler
+//! that produced it.
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let version = match naga_version() {
+        Ok(version) => version,
+        Err(message) => {
+            // a stale cache entry only costs a recompile once the version is known again, so this is no reason to fail
+            println!("cargo:warning=could not determine the naga version ({}), compiled shaders are cached without it", message);
+            "unknown".to_owned()
+        }
+    };
+    println!("cargo:rustc-env=NAGA_VERSION={}", version);
+}
+
+/// Asks `cargo metadata` which naga this package actually resolved to.
+fn naga_version() -> Result<String, String> {
+    let cargo = std::env::var("CARGO").map_err(|error| error.to_string())?;
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").map_err(|error| error.to_string())?;
+    let output = std::process::Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--manifest-path"])
+        .arg(std::path::Path::new(&manifest_dir).join("Cargo.toml"))
+        .output()
+        .map_err(|error| error.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+
+    let metadata : serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|error| error.to_string())?;
+    let package_name = std::env::var("CARGO_PKG_NAME").map_err(|error| error.to_string())?;
+    let packages = metadata["packages"].as_array().ok_or("no packages in cargo metadata")?;
+    let package_id = packages.iter()
+        .find(|package| {
+            package["name"] == package_name.as_str()
+                && package["manifest_path"].as_str().is_some_and(|path| path.starts_with(&manifest_dir))
+        })
+        .and_then(|package| package["id"].as_str())
+        .ok_or("this package is missing from cargo metadata")?;
+
+    let naga_id = metadata["resolve"]["nodes"].as_array()
+        .and_then(|nodes| nodes.iter().find(|node| node["id"] == package_id))
+        .and_then(|node| node["deps"].as_array())
+        .and_then(|deps| deps.iter().find(|dep| dep["name"] == "naga"))
+        .and_then(|dep| dep["pkg"].as_str())
+        .ok_or("naga is not a resolved dependency")?;
+
+    packages.iter()
+        .find(|package| package["id"] == naga_id)
+        .and_then(|package| package["version"].as_str())
+        .map(|version| version.to_owned())
+        .ok_or_else(|| "naga is missing from cargo metadata".to_owned())
+}
@@ -1,9 +1,14 @@
 #version 450
 
+// separate image and sampler, the runtime compiler has no combined image samplers
+layout(binding = 1) uniform texture2D texImage;
+layout(binding = 2) uniform sampler texSampler;
+
 layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = vec4(fragColor, 1.0);
-}
\ No newline at end of file
+    outColor = vec4(fragColor, 1.0) * texture(sampler2D(texImage, texSampler), fragTexCoord);
+}
@@ -0,0 +1,451 @@
+use std::cell::{Cell, RefCell};
+use ash::vk;
+use crate::error::{RendererError, VkResultExt};
+
+/// Size of the blocks reserved from large heaps. Heaps smaller than eight blocks get blocks of an eighth
+/// of their size instead.
+const DEFAULT_BLOCK_SIZE : vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Whether a resource is laid out linearly (buffers, linear images) or in an implementation defined way
+/// (optimal images). Neighbours of different kinds must not share a `bufferImageGranularity` page.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResourceKind {
+    Linear,
+    Optimal
+}
+
+/// A range of a block; `kind` is `None` while the range is free.
+#[derive(Clone, Copy)]
+struct Region {
+    offset : vk::DeviceSize,
+    size : vk::DeviceSize,
+    kind : Option<ResourceKind>
+}
+
+/// One `vkAllocateMemory` call, split into regions that cover it without gaps and sorted by offset.
+struct Block {
+    id : u64,
+    memory : vk::DeviceMemory,
+    size : vk::DeviceSize,
+    /// Start of the persistent mapping, or null if the memory type is not host visible.
+    mapped : *mut u8,
+    regions : Vec<Region>,
+    allocation_count : usize
+}
+
+/// A sub-allocated range of device memory. Must be returned with `Allocator::free`.
+pub struct Allocation {
+    pub memory : vk::DeviceMemory,
+    pub offset : vk::DeviceSize,
+    mapped : *mut u8,
+    memory_type_index : u32,
+    block_id : u64
+}
+
+impl Allocation {
+    /// Pointer to the start of the allocation if its memory is host visible. The whole block stays mapped
+    /// for its lifetime, so this never needs to be unmapped.
+    pub fn mapped_ptr(&self) -> Option<*mut u8> {
+        match self.mapped.is_null() {
+            true => None,
+            false => Some(self.mapped)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AllocatorStats {
+    pub block_count : usize,
+    pub allocation_count : usize,
+    pub reserved_bytes : vk::DeviceSize,
+    pub used_bytes : vk::DeviceSize,
+    pub free_region_count : usize,
+    pub largest_free_region : vk::DeviceSize
+}
+
+impl AllocatorStats {
+    /// 0 when all free memory is one contiguous region, approaching 1 as it splits into small holes.
+    pub fn fragmentation(&self) -> f32 {
+        let free_bytes = self.reserved_bytes - self.used_bytes;
+        match free_bytes {
+            0 => 0.0,
+            _ => 1.0 - self.largest_free_region as f32 / free_bytes as f32
+        }
+    }
+}
+
+impl std::fmt::Display for AllocatorStats {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} allocations in {} blocks, {} of {} bytes used, {} free regions (largest {} bytes, {:.0}% fragmented)",
+            self.allocation_count, self.block_count, self.used_bytes, self.reserved_bytes,
+            self.free_region_count, self.largest_free_region, self.fragmentation() * 100.0
+        )
+    }
+}
+
+/// Sub-allocates buffers and images from a few large blocks per memory type instead of calling
+/// `vkAllocateMemory` for every resource, which quickly runs into `maxMemoryAllocationCount`.
+///
+/// Allocation takes `&self` so that it can be used from the renderer's `&self` helpers.
+pub struct Allocator {
+    device : ash::Device,
+    memory_properties : vk::PhysicalDeviceMemoryProperties,
+    buffer_image_granularity : vk::DeviceSize,
+    /// Blocks of every memory type, indexed by memory type index.
+    blocks : RefCell<Vec<Vec<Block>>>,
+    next_block_id : Cell<u64>
+}
+
+impl Allocator {
+    pub fn new(
+        device : &ash::Device,
+        memory_properties : vk::PhysicalDeviceMemoryProperties,
+        buffer_image_granularity : vk::DeviceSize
+    ) -> Allocator {
+        Allocator {
+            device : device.clone(),
+            memory_properties,
+            buffer_image_granularity : std::cmp::max(buffer_image_granularity, 1),
+            blocks : RefCell::new((0..memory_properties.memory_type_count).map(|_| Vec::new()).collect()),
+            next_block_id : Cell::new(0)
+        }
+    }
+
+    pub fn find_memory_type(&self, type_filter : u32, properties : vk::MemoryPropertyFlags) -> Result<u32, RendererError> {
+        for i in 0..self.memory_properties.memory_type_count {
+            if ((type_filter & (1 << i)) > 0)
+            && (self.memory_properties.memory_types[i as usize].property_flags.contains(properties)) {
+                return Ok(i);
+            }
+        }
+
+        Err(RendererError::NoSuitableMemoryType)
+    }
+
+    /// Finds room for `requirements` in an existing block of the first matching memory type, reserving a
+    /// new block if none has space. Requests larger than a block get a block of their own.
+    pub fn allocate(
+        &self,
+        requirements : &vk::MemoryRequirements,
+        properties : vk::MemoryPropertyFlags,
+        kind : ResourceKind
+    ) -> Result<Allocation, RendererError> {
+        let memory_type_index = self.find_memory_type(requirements.memory_type_bits, properties)?;
+        let mut blocks = self.blocks.borrow_mut();
+        let type_blocks = &mut blocks[memory_type_index as usize];
+        let alignment = std::cmp::max(requirements.alignment, 1);
+
+        for block in type_blocks.iter_mut() {
+            if let Some(offset) = block.allocate(requirements.size, alignment, kind, self.buffer_image_granularity) {
+                return Ok(block.allocation(offset, memory_type_index));
+            }
+        }
+
+        let block_size = std::cmp::max(self.block_size(memory_type_index), requirements.size);
+        let mut block = self.create_block(memory_type_index, block_size)?;
+        // a fresh block starts with a single free region at offset 0, which satisfies any alignment
+        let offset = block.allocate(requirements.size, alignment, kind, self.buffer_image_granularity).unwrap();
+        let allocation = block.allocation(offset, memory_type_index);
+        type_blocks.push(block);
+        Ok(allocation)
+    }
+
+    /// Returns the range to its block. Empty blocks are released unless they are the last one of their type.
+    pub fn free(&self, allocation : Allocation) {
+        let mut blocks = self.blocks.borrow_mut();
+        if let Some(block) = free_in(&mut blocks[allocation.memory_type_index as usize], &allocation) {
+            self.destroy_block(block);
+        }
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats::default();
+        for block in self.blocks.borrow().iter().flat_map(|type_blocks| type_blocks.iter()) {
+            stats.block_count += 1;
+            stats.allocation_count += block.allocation_count;
+            stats.reserved_bytes += block.size;
+            for region in block.regions.iter() {
+                match region.kind {
+                    Some(_) => stats.used_bytes += region.size,
+                    None => {
+                        stats.free_region_count += 1;
+                        stats.largest_free_region = std::cmp::max(stats.largest_free_region, region.size);
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    /// Releases every block. Allocations that are still alive become dangling, so they are reported.
+    pub fn destroy(&mut self) {
+        let stats = self.stats();
+        if stats.allocation_count > 0 {
+            log::warn!("destroying the allocator with {} live allocations", stats.allocation_count);
+        }
+
+        let blocks = std::mem::take(self.blocks.get_mut());
+        for block in blocks.into_iter().flat_map(|type_blocks| type_blocks.into_iter()) {
+            self.destroy_block(block);
+        }
+    }
+
+    fn block_size(&self, memory_type_index : u32) -> vk::DeviceSize {
+        let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+        std::cmp::min(DEFAULT_BLOCK_SIZE, heap_size / 8)
+    }
+
+    fn create_block(&self, memory_type_index : u32, size : vk::DeviceSize) -> Result<Block, RendererError> {
+        let alloc_info = vk::MemoryAllocateInfo {
+            s_type : vk::StructureType::MEMORY_ALLOCATE_INFO,
+            p_next : std::ptr::null(),
+            allocation_size : size,
+            memory_type_index
+        };
+
+        let memory = unsafe{
+            self.device.allocate_memory(&alloc_info, None)
+            .context("failed to allocate memory block!")?
+        };
+
+        let host_visible = self.memory_properties.memory_types[memory_type_index as usize].property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let mapped = match host_visible {
+            false => std::ptr::null_mut(),
+            true => match unsafe{ self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) } {
+                Ok(mapped) => mapped as *mut u8,
+                Err(result) => {
+                    unsafe{ self.device.free_memory(memory, None); }
+                    return Err(result).context("Failed to map mamory");
+                }
+            }
+        };
+
+        let id = self.next_block_id.get();
+        self.next_block_id.set(id + 1);
+        log::debug!("reserved block {} of {} bytes from memory type {}", id, size, memory_type_index);
+
+        Ok(Block::new(id, memory, size, mapped))
+    }
+
+    fn destroy_block(&self, block : Block) {
+        unsafe{
+            // freeing mapped memory implicitly unmaps it
+            self.device.free_memory(block.memory, None);
+        }
+    }
+}
+
+impl Block {
+    fn new(id : u64, memory : vk::DeviceMemory, size : vk::DeviceSize, mapped : *mut u8) -> Block {
+        Block {
+            id,
+            memory,
+            size,
+            mapped,
+            regions : vec![Region { offset : 0, size, kind : None }],
+            allocation_count : 0
+        }
+    }
+
+    /// First fit search over the free regions. Returns the offset of the new allocation.
+    fn allocate(
+        &mut self,
+        size : vk::DeviceSize,
+        alignment : vk::DeviceSize,
+        kind : ResourceKind,
+        granularity : vk::DeviceSize
+    ) -> Option<vk::DeviceSize> {
+        for i in 0..self.regions.len() {
+            let region = self.regions[i];
+            if region.kind.is_some() {
+                continue;
+            }
+
+            let mut offset = align_up(region.offset, alignment);
+            if i > 0 {
+                let previous = self.regions[i - 1];
+                if conflicts(previous.kind, kind) && on_same_page(previous.offset + previous.size - 1, offset, granularity) {
+                    offset = align_up(offset, granularity);
+                }
+            }
+
+            let end = offset + size;
+            if end > region.offset + region.size {
+                continue;
+            }
+            if let Some(next) = self.regions.get(i + 1) {
+                if conflicts(next.kind, kind) && on_same_page(end - 1, next.offset, granularity) {
+                    continue;
+                }
+            }
+
+            // split into leading padding, the allocation and the remainder
+            let mut replacement = Vec::with_capacity(3);
+            if offset > region.offset {
+                replacement.push(Region { offset : region.offset, size : offset - region.offset, kind : None });
+            }
+            replacement.push(Region { offset, size, kind : Some(kind) });
+            if end < region.offset + region.size {
+                replacement.push(Region { offset : end, size : region.offset + region.size - end, kind : None });
+            }
+            self.regions.splice(i..i + 1, replacement);
+            self.allocation_count += 1;
+            return Some(offset);
+        }
+        None
+    }
+
+    /// Marks the region at `offset` free and merges it with free neighbours.
+    fn free(&mut self, offset : vk::DeviceSize) {
+        let mut i = match self.regions.binary_search_by_key(&offset, |region| region.offset) {
+            Ok(i) if self.regions[i].kind.is_some() => i,
+            _ => {
+                log::error!("freeing an unknown allocation at offset {} of block {}", offset, self.id);
+                return;
+            }
+        };
+        self.regions[i].kind = None;
+        self.allocation_count -= 1;
+
+        if i + 1 < self.regions.len() && self.regions[i + 1].kind.is_none() {
+            self.regions[i].size += self.regions[i + 1].size;
+            self.regions.remove(i + 1);
+        }
+        if i > 0 && self.regions[i - 1].kind.is_none() {
+            self.regions[i - 1].size += self.regions[i].size;
+            self.regions.remove(i);
+            i -= 1;
+        }
+        debug_assert!(self.regions[i].kind.is_none());
+    }
+
+    fn allocation(&self, offset : vk::DeviceSize, memory_type_index : u32) -> Allocation {
+        Allocation {
+            memory : self.memory,
+            offset,
+            mapped : match self.mapped.is_null() {
+                true => std::ptr::null_mut(),
+                false => unsafe{ self.mapped.add(offset as usize) }
+            },
+            memory_type_index,
+            block_id : self.id
+        }
+    }
+}
+
+/// Returns `allocation` to its block in `type_blocks`. A block left empty is removed and handed back for its
+/// memory to be released, unless it is the last block of its memory type.
+fn free_in(type_blocks : &mut Vec<Block>, allocation : &Allocation) -> Option<Block> {
+    let block_index = match type_blocks.iter().position(|block| block.id == allocation.block_id) {
+        Some(block_index) => block_index,
+        None => {
+            log::error!("freeing an allocation of an unknown block {}", allocation.block_id);
+            return None;
+        }
+    };
+
+    type_blocks[block_index].free(allocation.offset);
+    match type_blocks[block_index].allocation_count == 0 && type_blocks.len() > 1 {
+        true => Some(type_blocks.remove(block_index)),
+        false => None
+    }
+}
+
+/// Whether a neighbour of kind `neighbour` (`None` if free) needs granularity padding next to `kind`.
+fn conflicts(neighbour : Option<ResourceKind>, kind : ResourceKind) -> bool {
+    match neighbour {
+        Some(neighbour) => neighbour != kind,
+        None => false
+    }
+}
+
+/// Whether two byte offsets fall into the same `granularity` sized page.
+fn on_same_page(a : vk::DeviceSize, b : vk::DeviceSize, granularity : vk::DeviceSize) -> bool {
+    a / granularity == b / granularity
+}
+
+pub fn align_up(value : vk::DeviceSize, alignment : vk::DeviceSize) -> vk::DeviceSize {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id : u64, size : vk::DeviceSize) -> Block {
+        Block::new(id, vk::DeviceMemory::null(), size, std::ptr::null_mut())
+    }
+
+    /// (offset, size, allocated) of every region, in order.
+    fn layout(block : &Block) -> Vec<(vk::DeviceSize, vk::DeviceSize, bool)> {
+        block.regions.iter().map(|region| (region.offset, region.size, region.kind.is_some())).collect()
+    }
+
+    #[test]
+    fn freed_ranges_are_reused_and_merged() {
+        let mut block = block(0, 1024);
+        let first = block.allocate(256, 16, ResourceKind::Linear, 1).unwrap();
+        let second = block.allocate(256, 16, ResourceKind::Linear, 1).unwrap();
+        assert_eq!((first, second), (0, 256));
+
+        block.free(first);
+        assert_eq!(block.allocate(128, 16, ResourceKind::Linear, 1), Some(0));
+        assert_eq!(layout(&block), vec![(0, 128, true), (128, 128, false), (256, 256, true), (512, 512, false)]);
+
+        block.free(0);
+        block.free(second);
+        assert_eq!(layout(&block), vec![(0, 1024, false)]);
+        assert_eq!(block.allocation_count, 0);
+    }
+
+    #[test]
+    fn allocations_respect_alignment_and_size() {
+        let mut block = block(0, 1024);
+        block.allocate(10, 1, ResourceKind::Linear, 1).unwrap();
+        assert_eq!(block.allocate(100, 256, ResourceKind::Linear, 1), Some(256));
+        assert_eq!(block.allocate(1024, 1, ResourceKind::Linear, 1), None);
+    }
+
+    #[test]
+    fn linear_and_optimal_neighbours_do_not_share_a_page() {
+        let mut block = block(0, 4096);
+        assert_eq!(block.allocate(100, 16, ResourceKind::Linear, 1024), Some(0));
+        assert_eq!(block.allocate(100, 16, ResourceKind::Linear, 1024), Some(112));
+        // padded past the page the buffers end in
+        assert_eq!(block.allocate(100, 16, ResourceKind::Optimal, 1024), Some(1024));
+        // the same kind packs tightly
+        assert_eq!(block.allocate(100, 16, ResourceKind::Optimal, 1024), Some(1136));
+
+        // the hole at the start is big enough, but the buffer right after it shares its page
+        block.free(0);
+        assert_eq!(block.allocate(50, 16, ResourceKind::Optimal, 1024), Some(1248));
+        assert_eq!(block.allocate(50, 16, ResourceKind::Linear, 1024), Some(0));
+    }
+
+    #[test]
+    fn empty_blocks_are_released_except_the_last() {
+        let mut type_blocks = vec![block(0, 1024), block(1, 1024)];
+        let offsets = [
+            type_blocks[0].allocate(512, 16, ResourceKind::Linear, 1).unwrap(),
+            type_blocks[0].allocate(512, 16, ResourceKind::Linear, 1).unwrap(),
+            type_blocks[1].allocate(512, 16, ResourceKind::Linear, 1).unwrap()
+        ];
+        let allocations : Vec<Allocation> = [(0, offsets[0]), (0, offsets[1]), (1, offsets[2])].iter()
+            .map(|&(block_index, offset)| type_blocks[block_index].allocation(offset, 0))
+            .collect();
+
+        assert!(free_in(&mut type_blocks, &allocations[0]).is_none());
+        let released = free_in(&mut type_blocks, &allocations[1]).unwrap();
+        assert_eq!(released.id, 0);
+        assert_eq!(type_blocks.len(), 1);
+
+        // the last block is kept for the next allocation
+        assert!(free_in(&mut type_blocks, &allocations[2]).is_none());
+        assert_eq!(type_blocks.len(), 1);
+        assert_eq!(type_blocks[0].allocation_count, 0);
+    }
+}
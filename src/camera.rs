@@ -0,0 +1,280 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Rad, Vector3};
+
+/// cgmath builds OpenGL style projections; this flips Y and remaps depth from [-1, 1] to [0, 1].
+#[rustfmt::skip]
+const OPENGL_TO_VULKAN_MATRIX : Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, -1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Scenes are Z-up, see `scene::Scene::load_gltf`.
+const UP : Vector3<f32> = Vector3::new(0.0, 0.0, 1.0);
+
+/// Pitch stops just short of straight up or down, where the view basis would degenerate.
+const MAX_PITCH : Rad<f32> = Rad(1.55);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CameraMode {
+    /// Rotates around `target` at `distance`; movement keys pan the target.
+    Orbit,
+    /// Rotates in place; movement keys move the eye.
+    Fly
+}
+
+/// Movement keys that are currently held down.
+#[derive(Default)]
+struct HeldKeys {
+    forward : bool,
+    back : bool,
+    left : bool,
+    right : bool,
+    up : bool,
+    down : bool
+}
+
+/// Interactive camera fed from GLFW window events.
+///
+/// Controls: WASD move, Q/E move down/up, dragging with the left mouse button (or moving the mouse while the
+/// cursor is captured) looks around, the scroll wheel zooms in orbit mode and changes the speed in fly mode,
+/// Tab switches between orbit and fly, C toggles cursor capture and Escape releases it.
+pub struct Camera {
+    pub mode : CameraMode,
+    /// Orbit center; in fly mode the point `distance` in front of the eye.
+    target : Point3<f32>,
+    distance : f32,
+    /// Rotation of the viewing direction around `UP`, measured from +X.
+    yaw : Rad<f32>,
+    /// Elevation of the viewing direction above the XY plane.
+    pitch : Rad<f32>,
+    pub fov_y : Rad<f32>,
+    pub near : f32,
+    pub far : f32,
+    /// Units per second.
+    pub move_speed : f32,
+    /// Radians per pixel of cursor movement.
+    pub look_sensitivity : f32,
+    held : HeldKeys,
+    dragging : bool,
+    cursor_captured : bool,
+    last_cursor : Option<(f64, f64)>
+}
+
+impl Camera {
+    /// Looks at `target` from `eye`, starting in orbit mode.
+    pub fn look_at(eye : Point3<f32>, target : Point3<f32>) -> Camera {
+        let offset = target - eye;
+        let distance = offset.magnitude();
+        let direction = offset / distance;
+
+        Camera {
+            mode : CameraMode::Orbit,
+            target,
+            distance,
+            yaw : Rad(direction.y.atan2(direction.x)),
+            pitch : Rad(direction.z.asin()),
+            fov_y : Rad::from(cgmath::Deg(45.0)),
+            near : 0.1,
+            far : 100.0,
+            move_speed : 2.0,
+            look_sensitivity : 0.005,
+            held : HeldKeys::default(),
+            dragging : false,
+            cursor_captured : false,
+            last_cursor : None
+        }
+    }
+
+    /// Unit vector the camera looks along.
+    pub fn forward(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.pitch.0.cos() * self.yaw.0.cos(),
+            self.pitch.0.cos() * self.yaw.0.sin(),
+            self.pitch.0.sin()
+        )
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.target - self.forward() * self.distance
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.eye(), self.forward(), UP)
+    }
+
+    /// Perspective projection into Vulkan clip space, where Y points down and depth ranges over [0, 1].
+    pub fn projection(&self, aspect : f32) -> Matrix4<f32> {
+        OPENGL_TO_VULKAN_MATRIX * cgmath::perspective(self.fov_y, aspect, self.near, self.far)
+    }
+
+    /// Updates the held keys, cursor capture and orientation from a single window event.
+    pub fn handle_event(&mut self, window : &mut glfw::Window, event : &glfw::WindowEvent) {
+        match *event {
+            glfw::WindowEvent::Key(key, _, action, _) => {
+                let pressed = action != glfw::Action::Release;
+                match key {
+                    glfw::Key::W => self.held.forward = pressed,
+                    glfw::Key::S => self.held.back = pressed,
+                    glfw::Key::A => self.held.left = pressed,
+                    glfw::Key::D => self.held.right = pressed,
+                    glfw::Key::E => self.held.up = pressed,
+                    glfw::Key::Q => self.held.down = pressed,
+                    glfw::Key::Tab if action == glfw::Action::Press => {
+                        self.mode = match self.mode {
+                            CameraMode::Orbit => CameraMode::Fly,
+                            CameraMode::Fly => CameraMode::Orbit
+                        };
+                        log::info!("camera mode: {:?}", self.mode);
+                    },
+                    glfw::Key::C if action == glfw::Action::Press => {
+                        let captured = !self.cursor_captured;
+                        self.set_cursor_captured(window, captured);
+                    },
+                    glfw::Key::Escape if action == glfw::Action::Press => self.set_cursor_captured(window, false),
+                    _ => {}
+                }
+            },
+            glfw::WindowEvent::MouseButton(glfw::MouseButtonLeft, action, _) => {
+                self.dragging = action == glfw::Action::Press;
+            },
+            glfw::WindowEvent::CursorPos(x, y) => {
+                if let Some((last_x, last_y)) = self.last_cursor {
+                    if self.dragging || self.cursor_captured {
+                        self.rotate((x - last_x) as f32, (y - last_y) as f32);
+                    }
+                }
+                self.last_cursor = Some((x, y));
+            },
+            glfw::WindowEvent::Scroll(_, y_offset) => match self.mode {
+                CameraMode::Orbit => self.distance = (self.distance * 0.9f32.powf(y_offset as f32)).max(self.near),
+                CameraMode::Fly => self.move_speed = (self.move_speed * 1.2f32.powf(y_offset as f32)).max(0.01)
+            },
+            // the keys are lost when the window loses focus, so a release event may never come
+            glfw::WindowEvent::Focus(false) => {
+                self.held = HeldKeys::default();
+                self.dragging = false;
+            },
+            _ => {}
+        }
+    }
+
+    /// Applies the held movement keys for a frame that took `delta_time` seconds.
+    pub fn update(&mut self, delta_time : f32) {
+        let forward = self.forward();
+        let right = forward.cross(UP).normalize();
+
+        let mut direction = Vector3::new(0.0, 0.0, 0.0);
+        if self.held.forward { direction += forward; }
+        if self.held.back { direction -= forward; }
+        if self.held.right { direction += right; }
+        if self.held.left { direction -= right; }
+        if self.held.up { direction += UP; }
+        if self.held.down { direction -= UP; }
+
+        if direction.magnitude2() > 0.0 {
+            // the eye and the target move together, which pans in orbit mode and flies in fly mode
+            self.target += direction.normalize() * self.move_speed * delta_time;
+        }
+    }
+
+    /// Turns by a cursor movement of `dx`, `dy` pixels. Orbit mode keeps the target fixed and moves the eye,
+    /// fly mode keeps the eye fixed and moves the target.
+    fn rotate(&mut self, dx : f32, dy : f32) {
+        let eye = self.eye();
+        self.yaw -= Rad(dx * self.look_sensitivity);
+        // screen Y grows downwards, so moving the cursor up looks up
+        self.pitch = Rad((self.pitch.0 - dy * self.look_sensitivity).clamp(-MAX_PITCH.0, MAX_PITCH.0));
+
+        if self.mode == CameraMode::Fly {
+            self.target = eye + self.forward() * self.distance;
+        }
+    }
+
+    fn set_cursor_captured(&mut self, window : &mut glfw::Window, captured : bool) {
+        self.cursor_captured = captured;
+        window.set_cursor_mode(match captured {
+            true => glfw::CursorMode::Disabled,
+            false => glfw::CursorMode::Normal
+        });
+        // the cursor jumps when the mode changes, which must not turn the camera
+        self.last_cursor = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{EuclideanSpace, Vector4};
+
+    fn assert_close(actual : f32, expected : f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} is not close to {}", actual, expected);
+    }
+
+    fn assert_points_close(actual : Point3<f32>, expected : Point3<f32>) {
+        assert!((actual - expected).magnitude() < 1e-4, "{:?} is not close to {:?}", actual, expected);
+    }
+
+    fn camera() -> Camera {
+        Camera::look_at(Point3::new(2.0, 2.0, 2.0), Point3::origin())
+    }
+
+    #[test]
+    fn look_at_reproduces_the_eye() {
+        let camera = camera();
+        assert_points_close(camera.eye(), Point3::new(2.0, 2.0, 2.0));
+        assert_close(camera.forward().magnitude(), 1.0);
+        assert_close(camera.forward().dot(Vector3::new(-1.0, -1.0, -1.0).normalize()), 1.0);
+    }
+
+    #[test]
+    fn orbit_keeps_the_target_and_fly_keeps_the_eye() {
+        let mut orbit = camera();
+        orbit.rotate(100.0, 40.0);
+        assert_points_close(orbit.target, Point3::origin());
+        assert_close((orbit.eye() - orbit.target).magnitude(), orbit.distance);
+
+        let mut fly = camera();
+        fly.mode = CameraMode::Fly;
+        fly.rotate(100.0, 40.0);
+        assert_points_close(fly.eye(), Point3::new(2.0, 2.0, 2.0));
+        assert!((fly.target - Point3::origin()).magnitude() > 0.1);
+    }
+
+    #[test]
+    fn pitch_is_clamped_short_of_the_poles() {
+        let mut camera = camera();
+        camera.rotate(0.0, -1.0e6);
+        assert_close(camera.pitch.0, MAX_PITCH.0);
+        camera.rotate(0.0, 1.0e6);
+        assert_close(camera.pitch.0, -MAX_PITCH.0);
+    }
+
+    #[test]
+    fn movement_pans_eye_and_target_together() {
+        let mut camera = camera();
+        let forward = camera.forward();
+        camera.held.forward = true;
+        camera.update(0.5);
+
+        let moved = forward * camera.move_speed * 0.5;
+        assert_points_close(camera.target, Point3::origin() + moved);
+        assert_points_close(camera.eye(), Point3::new(2.0, 2.0, 2.0) + moved);
+    }
+
+    #[test]
+    fn projection_flips_y_and_maps_depth_to_zero_one() {
+        let camera = camera();
+        let projection = camera.projection(1.0);
+        let to_ndc = |point : Vector4<f32>| {
+            let clip = projection * point;
+            clip.truncate() / clip.w
+        };
+
+        assert_close(to_ndc(Vector4::new(0.0, 0.0, -camera.near, 1.0)).z, 0.0);
+        assert_close(to_ndc(Vector4::new(0.0, 0.0, -camera.far, 1.0)).z, 1.0);
+        // up in view space is down in Vulkan's framebuffer coordinates
+        assert!(to_ndc(Vector4::new(0.0, 1.0, -5.0, 1.0)).y < 0.0);
+        assert!(to_ndc(Vector4::new(1.0, 0.0, -5.0, 1.0)).x > 0.0);
+    }
+}
@@ -0,0 +1,99 @@
+use ash::vk;
+
+#[derive(Debug)]
+pub enum RendererError {
+    LoaderMissing(ash::LoadingError),
+    WindowSystem(String),
+    InvalidArgument(String),
+    NoSuitableDevice,
+    NoSuitableMemoryType,
+    SurfaceLost(&'static str),
+    DeviceLost(&'static str),
+    OutOfDeviceMemory(&'static str),
+    ShaderLoad {
+        path : std::path::PathBuf,
+        source : std::io::Error
+    },
+    ShaderCompile {
+        path : std::path::PathBuf,
+        line : Option<u32>,
+        message : String
+    },
+    /// The shaders declare resources or inputs the renderer does not provide.
+    ShaderInterface(String),
+    ImageLoad {
+        path : std::path::PathBuf,
+        message : String
+    },
+    ImageWrite {
+        path : std::path::PathBuf,
+        message : String
+    },
+    ModelLoad {
+        path : std::path::PathBuf,
+        message : String
+    },
+    Vulkan {
+        context : &'static str,
+        result : vk::Result
+    }
+}
+
+impl std::fmt::Display for RendererError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RendererError::LoaderMissing(error) => write!(f, "failed to load the Vulkan loader: {}", error),
+            RendererError::WindowSystem(message) => write!(f, "window system error: {}", message),
+            RendererError::InvalidArgument(message) => write!(f, "invalid argument: {}", message),
+            RendererError::NoSuitableDevice => write!(f, "failed to find GPUs with Vulkan support!"),
+            RendererError::NoSuitableMemoryType => write!(f, "failed to find suitable memory type!"),
+            RendererError::SurfaceLost(context) => write!(f, "{}: surface lost", context),
+            RendererError::DeviceLost(context) => write!(f, "{}: device lost", context),
+            RendererError::OutOfDeviceMemory(context) => write!(f, "{}: out of device memory", context),
+            RendererError::ShaderLoad { path, source } => write!(f, "failed to load shader {}: {}", path.display(), source),
+            RendererError::ShaderCompile { path, line : Some(line), message } => write!(f, "failed to compile shader {}:{}: {}", path.display(), line, message),
+            RendererError::ShaderCompile { path, line : None, message } => write!(f, "failed to compile shader {}: {}", path.display(), message),
+            RendererError::ShaderInterface(message) => write!(f, "shader interface mismatch: {}", message),
+            RendererError::ImageLoad { path, message } => write!(f, "failed to load image {}: {}", path.display(), message),
+            RendererError::ImageWrite { path, message } => write!(f, "failed to write image {}: {}", path.display(), message),
+            RendererError::ModelLoad { path, message } => write!(f, "failed to load model {}: {}", path.display(), message),
+            RendererError::Vulkan { context, result } => write!(f, "{}: {}", context, result)
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::LoaderMissing(error) => Some(error),
+            RendererError::ShaderLoad { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+/// Attaches a description of the failed operation to a raw `vk::Result`.
+pub trait VkResultExt<T> {
+    fn context(self, context : &'static str) -> Result<T, RendererError>;
+}
+
+impl<T> VkResultExt<T> for Result<T, vk::Result> {
+    fn context(self, context : &'static str) -> Result<T, RendererError> {
+        self.map_err(|result| match result {
+            vk::Result::ERROR_SURFACE_LOST_KHR => RendererError::SurfaceLost(context),
+            vk::Result::ERROR_DEVICE_LOST => RendererError::DeviceLost(context),
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => RendererError::OutOfDeviceMemory(context),
+            result => RendererError::Vulkan { context, result }
+        })
+    }
+}
+
+impl<T> VkResultExt<T> for Result<T, ash::InstanceError> {
+    fn context(self, context : &'static str) -> Result<T, RendererError> {
+        self.map_err(|error| match error {
+            ash::InstanceError::VkError(result) => result,
+            // the instance was created, but some of its entry points could not be loaded
+            ash::InstanceError::LoadError(_) => vk::Result::ERROR_INITIALIZATION_FAILED
+        }).context(context)
+    }
+}
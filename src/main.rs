@@ -1,8 +1,28 @@
 extern crate glfw;
 extern crate ash;
+#[cfg(target_os = "windows")]
 extern crate winapi;
 extern crate memoffset;
 extern crate cgmath;
+extern crate png;
+extern crate log;
+extern crate env_logger;
+extern crate image;
+extern crate tobj;
+extern crate gltf;
+extern crate dirs;
+extern crate naga;
+
+mod allocator;
+mod camera;
+mod error;
+mod pipeline;
+mod pipeline_cache;
+mod reflection;
+mod scene;
+mod shader_compiler;
+mod shader_watcher;
+mod upload;
 
 use std::io::Read;
 use std::mem::swap;
@@ -10,23 +30,65 @@ use std::ops::{Add, Deref};
 use std::{ops::Index, sync::mpsc::Receiver};
 use std::ffi::CString;
 use std::os::raw::c_char;
-use ash::vk::{ClearColorValue, CommandBufferUsageFlags, DescriptorSetLayoutBinding, PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo, PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateFlags, PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, VertexInputAttributeDescription, VertexInputBindingDescription};
+use ash::vk::{ClearColorValue, CommandBufferUsageFlags};
 use glfw::Glfw;
 use ash::{Instance, vk};
+use ash::vk::Handle;
+#[cfg(target_os = "windows")]
 use winapi::um::libloaderapi::GetModuleHandleW;
-
+use allocator::{Allocation, ResourceKind};
+use error::{RendererError, VkResultExt};
+
+/// Watched for changes while the renderer runs in a window, see `reload_shaders`.
+const SHADER_DIRECTORY : &str = "shaders";
+const VERT_SHADER_PATH : &str = "shaders/shader.vert";
+const FRAG_SHADER_PATH : &str = "shaders/shader.frag";
+/// Set 0 bindings `create_descriptor_sets` knows how to write, one list per accepted shader interface. The
+/// material texture is either a combined image sampler, as precompiled SPIR-V declares it, or a separate image
+/// and sampler, as shaders compiled at runtime have to since naga cannot emit combined image samplers.
+const SCENE_DESCRIPTOR_BINDINGS : [&[(u32, vk::DescriptorType)]; 2] = [
+    &[(0, vk::DescriptorType::UNIFORM_BUFFER), (1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)],
+    &[(0, vk::DescriptorType::UNIFORM_BUFFER), (1, vk::DescriptorType::SAMPLED_IMAGE), (2, vk::DescriptorType::SAMPLER)]
+];
+/// Extensions of the files in `SHADER_DIRECTORY` that `load_shader` understands.
+const SHADER_EXTENSIONS : &[&str] = &["vert", "frag", "comp", "wgsl", "spv"];
+
+
+/// Per-frame uniforms shared by every draw.
+#[repr(C)]
 struct UniformBufferObject {
-    model : cgmath::Matrix4<f32>,
     view : cgmath::Matrix4<f32>,
     proj : cgmath::Matrix4<f32>
 }
 
+/// Per-draw data, pushed before every draw call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DrawPushConstants {
+    model : cgmath::Matrix4<f32>
+}
+
+unsafe impl pipeline::PushConstants for DrawPushConstants {}
+
+#[derive(Clone, Copy)]
 struct Vertex {
-    pos: [f32; 2],
+    pos: [f32; 3],
     color: [f32; 3],
+    tex_coord: [f32; 2],
+    normal: [f32; 3],
 }
 
 impl Vertex {
+    /// Bit patterns of every component, so vertices can be compared and hashed exactly.
+    fn to_bits(&self) -> [u32; 11] {
+        [
+            self.pos[0].to_bits(), self.pos[1].to_bits(), self.pos[2].to_bits(),
+            self.color[0].to_bits(), self.color[1].to_bits(), self.color[2].to_bits(),
+            self.tex_coord[0].to_bits(), self.tex_coord[1].to_bits(),
+            self.normal[0].to_bits(), self.normal[1].to_bits(), self.normal[2].to_bits()
+        ]
+    }
+
     fn get_binding_destcription() -> vk::VertexInputBindingDescription {
         return vk::VertexInputBindingDescription {
             binding : 0,
@@ -35,12 +97,12 @@ impl Vertex {
         };
     }
 
-    fn get_attribute_descripyions() -> [vk::VertexInputAttributeDescription; 2] {
+    fn get_attribute_descripyions() -> [vk::VertexInputAttributeDescription; 4] {
         return [
             vk::VertexInputAttributeDescription {
                 binding : 0,
                 location : 0,
-                format : vk::Format::R32G32_SFLOAT,
+                format : vk::Format::R32G32B32_SFLOAT,
                 offset : memoffset::offset_of!(Self, pos) as u32,
             },
             
@@ -49,25 +111,266 @@ impl Vertex {
                 location : 1,
                 format : vk::Format::R32G32B32_SFLOAT,
                 offset : memoffset::offset_of!(Self, color) as u32
+            },
+
+            vk::VertexInputAttributeDescription {
+                binding : 0,
+                location : 2,
+                format : vk::Format::R32G32_SFLOAT,
+                offset : memoffset::offset_of!(Self, tex_coord) as u32
+            },
+
+            vk::VertexInputAttributeDescription {
+                binding : 0,
+                location : 3,
+                format : vk::Format::R32G32B32_SFLOAT,
+                offset : memoffset::offset_of!(Self, normal) as u32
             }
         ]
     }
 }
 
+impl PartialEq for Vertex {
+    fn eq(&self, other : &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+impl Eq for Vertex {}
+
+impl std::hash::Hash for Vertex {
+    fn hash<H : std::hash::Hasher>(&self, state : &mut H) {
+        self.to_bits().hash(state);
+    }
+}
+
+/// Vertex and index buffers of one scene primitive.
+struct GpuPrimitive {
+    vertex_buffer : vk::Buffer,
+    vertex_buffer_allocation : Allocation,
+    index_buffer : vk::Buffer,
+    index_buffer_allocation : Allocation,
+    index_count : u32,
+    index_type : vk::IndexType,
+    material : usize
+}
+
+struct Texture {
+    image : vk::Image,
+    allocation : Allocation,
+    view : vk::ImageView,
+    mip_levels : u32
+}
+
+/// One indexed draw of a primitive placed by a node, whose world transform is pushed as its model matrix.
+struct DrawCommand {
+    primitive : usize,
+    node : usize,
+    /// Drawn with alpha blending after every opaque draw.
+    transparent : bool
+}
+
+/// The pipelines drawing the scene, all built from the same vertex and fragment shader.
+struct ScenePipelines {
+    opaque : vk::Pipeline,
+    transparent : vk::Pipeline,
+    /// Only created when the device supports `fillModeNonSolid`.
+    wireframe : Option<vk::Pipeline>
+}
+
+impl ScenePipelines {
+    unsafe fn destroy(self, device : &ash::Device) {
+        device.destroy_pipeline(self.opaque, None);
+        device.destroy_pipeline(self.transparent, None);
+        if let Some(wireframe) = self.wireframe {
+            device.destroy_pipeline(wireframe, None);
+        }
+    }
+}
+
 struct SwapChainSupportDetails{
     capabilities : vk::SurfaceCapabilitiesKHR,
     formats : Vec<vk::SurfaceFormatKHR>,
     present_modes : Vec<vk::PresentModeKHR>
 }
 
+const DEFAULT_TEXTURE : &str = "textures/texture.png";
+
+/// Accepted range of `--frames-in-flight`.
+const MAX_FRAMES_IN_FLIGHT_RANGE : std::ops::RangeInclusive<usize> = 1..=4;
+
+/// Environment variable holding the same GPU selector as `--gpu`; the command line takes precedence.
+const GPU_OVERRIDE_ENV : &str = "RUST_VULKAN_GPU";
+
+/// Picks a physical device explicitly instead of taking the highest scoring one.
+enum GpuSelector {
+    Index(usize),
+    PipelineCacheUuid([u8; vk::UUID_SIZE]),
+    NameContains(String)
+}
+
+impl GpuSelector {
+    /// Digits select by enumeration index, 32 hex digits (dashes allowed) by `pipeline_cache_uuid`,
+    /// anything else by a case-insensitive device name substring.
+    fn parse(selector : &str) -> GpuSelector {
+        if let Ok(index) = selector.parse::<usize>() {
+            return GpuSelector::Index(index);
+        }
+
+        let hex : String = selector.chars().filter(|c| *c != '-').collect();
+        if hex.len() == vk::UUID_SIZE * 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut uuid = [0u8; vk::UUID_SIZE];
+            for (idx, byte) in uuid.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).unwrap();
+            }
+            return GpuSelector::PipelineCacheUuid(uuid);
+        }
+
+        GpuSelector::NameContains(selector.to_lowercase())
+    }
+
+    fn matches(&self, index : usize, properties : &vk::PhysicalDeviceProperties) -> bool {
+        match self {
+            GpuSelector::Index(selected) => *selected == index,
+            GpuSelector::PipelineCacheUuid(uuid) => *uuid == properties.pipeline_cache_uuid,
+            GpuSelector::NameContains(name) => device_name(properties).to_lowercase().contains(name.as_str())
+        }
+    }
+}
+
+/// Filtering and addressing used by the texture sampler.
+struct SamplerSettings {
+    filter : vk::Filter,
+    address_mode : vk::SamplerAddressMode,
+    /// Only honoured when the device supports `samplerAnisotropy`.
+    anisotropy : bool
+}
+
+struct AppOptions {
+    headless : bool,
+    frame_count : u32,
+    output : Option<std::path::PathBuf>,
+    validation : bool,
+    gpu : Option<String>,
+    /// Replaces the textures of every material, including the built-in geometry's.
+    texture : Option<std::path::PathBuf>,
+    sampler : SamplerSettings,
+    model : Option<std::path::PathBuf>,
+    /// Number of frames the CPU may record ahead of the GPU. Every per-frame resource is sized by it.
+    max_frames_in_flight : usize,
+    /// Records every command buffer once up front instead of every frame, to measure the recording cost.
+    prerecord_commands : bool
+}
+
+impl AppOptions {
+    fn from_args<I : Iterator<Item = String>>(mut args : I) -> Result<AppOptions, RendererError> {
+        let mut options = AppOptions {
+            headless : false,
+            frame_count : 3,
+            output : None,
+            validation : false,
+            gpu : std::env::var(GPU_OVERRIDE_ENV).ok().filter(|x| !x.is_empty()),
+            texture : None,
+            sampler : SamplerSettings {
+                filter : vk::Filter::LINEAR,
+                address_mode : vk::SamplerAddressMode::REPEAT,
+                anisotropy : true
+            },
+            model : None,
+            max_frames_in_flight : 2,
+            prerecord_commands : false
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--validation" => options.validation = true,
+                "--frames" => {
+                    options.frame_count = args.next()
+                        .and_then(|x| x.parse().ok())
+                        .ok_or_else(|| RendererError::InvalidArgument("--frames expects a frame count".to_owned()))?;
+                },
+                "--output" => {
+                    options.output = Some(
+                        args.next()
+                        .ok_or_else(|| RendererError::InvalidArgument("--output expects a file path".to_owned()))?
+                        .into()
+                    );
+                },
+                "--gpu" => {
+                    options.gpu = Some(
+                        args.next()
+                        .ok_or_else(|| RendererError::InvalidArgument("--gpu expects an index, uuid or device name".to_owned()))?
+                    );
+                },
+                "--texture" => {
+                    options.texture = Some(
+                        args.next()
+                        .ok_or_else(|| RendererError::InvalidArgument("--texture expects a file path".to_owned()))?
+                        .into()
+                    );
+                },
+                "--model" => {
+                    options.model = Some(
+                        args.next()
+                        .ok_or_else(|| RendererError::InvalidArgument("--model expects a file path".to_owned()))?
+                        .into()
+                    );
+                },
+                "--filter" => {
+                    options.sampler.filter = match args.next().as_deref() {
+                        Some("linear") => vk::Filter::LINEAR,
+                        Some("nearest") => vk::Filter::NEAREST,
+                        _ => return Err(RendererError::InvalidArgument("--filter expects linear or nearest".to_owned()))
+                    };
+                },
+                "--address-mode" => {
+                    options.sampler.address_mode = match args.next().as_deref() {
+                        Some("repeat") => vk::SamplerAddressMode::REPEAT,
+                        Some("mirrored-repeat") => vk::SamplerAddressMode::MIRRORED_REPEAT,
+                        Some("clamp-to-edge") => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                        Some("clamp-to-border") => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+                        _ => return Err(RendererError::InvalidArgument(
+                            "--address-mode expects repeat, mirrored-repeat, clamp-to-edge or clamp-to-border".to_owned()
+                        ))
+                    };
+                },
+                "--no-anisotropy" => options.sampler.anisotropy = false,
+                "--prerecorded" => options.prerecord_commands = true,
+                "--frames-in-flight" => {
+                    options.max_frames_in_flight = args.next()
+                        .and_then(|x| x.parse().ok())
+                        .filter(|x| MAX_FRAMES_IN_FLIGHT_RANGE.contains(x))
+                        .ok_or_else(|| RendererError::InvalidArgument(format!(
+                            "--frames-in-flight expects a count between {} and {}",
+                            MAX_FRAMES_IN_FLIGHT_RANGE.start(), MAX_FRAMES_IN_FLIGHT_RANGE.end()
+                        )))?;
+                },
+                _ => return Err(RendererError::InvalidArgument(format!("unknown argument: {}", arg)))
+            }
+        }
+
+        // the offscreen target is only written by rendering, so it has to happen at least once
+        if options.headless && options.frame_count == 0 {
+            return Err(RendererError::InvalidArgument("--headless needs at least one frame, --frames must not be 0".to_owned()));
+        }
+
+        Ok(options)
+    }
+}
+
 struct HelloTriangleApplication {
-    glfw : Glfw,
+    options : AppOptions,
+    glfw : Option<Glfw>,
     window : Option<glfw::Window>,
     event : Option<Receiver<(f64, glfw::WindowEvent)>>,
     width : u32,
     height : u32,
     vk_entry : ash::Entry,
     instance : Option<Instance>,
+    enabled_instance_extensions : Vec<String>,
+    debug_utils : Option<ash::extensions::ext::DebugUtils>,
+    debug_messenger : Option<vk::DebugUtilsMessengerEXT>,
     physical_device : Option<vk::PhysicalDevice>,
     device : Option<ash::Device>,
     graphics_queue : Option<vk::Queue>,
@@ -76,47 +379,95 @@ struct HelloTriangleApplication {
     swap_chain : Option<vk::SwapchainKHR>,
     swap_chain_images : Option<Vec<vk::Image>>,
     swap_chain_image_format : Option<vk::Format>,
+    /// Screenshots need TRANSFER_SRC, which surfaces do not have to support.
+    swap_chain_image_usage : vk::ImageUsageFlags,
     swap_chain_extent : Option<vk::Extent2D>,
     swap_chain_image_views : Vec<vk::ImageView>,
+    offscreen_image_allocation : Option<Allocation>,
+    depth_image : Option<vk::Image>,
+    depth_image_allocation : Option<Allocation>,
+    depth_image_view : Option<vk::ImageView>,
+    textures : Vec<Texture>,
+    /// Index into `textures` for every scene material.
+    material_textures : Vec<usize>,
+    texture_sampler : Option<vk::Sampler>,
     render_pass : Option<vk::RenderPass>,
     descriptor_set_layout : Option<vk::DescriptorSetLayout>,
+    /// Resources and vertex inputs of the scene shaders the layouts were created from.
+    pipeline_interface : Option<reflection::PipelineInterface>,
+    descriptor_pool : Option<vk::DescriptorPool>,
+    descriptor_sets : Vec<vk::DescriptorSet>,
+    pipeline_cache : Option<pipeline_cache::PipelineCache>,
     pipeline_layout : Option<vk::PipelineLayout>,
-    graphics_pipeline : Option<vk::Pipeline>,
+    pipelines : Option<ScenePipelines>,
+    /// Reloads changed shaders, only when running in a window.
+    shader_watcher : Option<shader_watcher::ShaderWatcher>,
+    /// Draw opaque primitives as wireframes, toggled with F1.
+    wireframe : bool,
     swap_chain_frame_buffers : Vec<vk::Framebuffer>,
+    allocator : Option<allocator::Allocator>,
     command_pool : Option<vk::CommandPool>,
+    upload_context : Option<upload::UploadContext>,
+    /// Every memory heap is device local, so DEVICE_LOCAL buffers can be written without staging.
+    unified_memory : bool,
+    /// Pre-recorded command buffers, only used with `--prerecorded`.
     command_buffers : Option<Vec<vk::CommandBuffer>>,
+    /// One pool per frame in flight, reset before the frame's command buffer is recorded again.
+    frame_command_pools : Vec<vk::CommandPool>,
+    frame_command_buffers : Vec<vk::CommandBuffer>,
     image_available_semaphores : Vec<vk::Semaphore>,
     render_finished_semaphores : Vec<vk::Semaphore>,
     in_flight_fences : Vec<vk::Fence>,
     images_in_flight : Vec<vk::Fence>,
     current_frame : usize,
-    vertices : Vec<Vertex>,
-    indices : Vec<u16>,
-    vertex_buffer : Option<vk::Buffer>,
-    vertex_buffer_memory : Option<vk::DeviceMemory>,
-    index_buffer : Option<vk::Buffer>,
-    index_buffer_memory : Option<vk::DeviceMemory>,
+    /// Saved by the next `draw_frame`, while it still owns the swap chain image.
+    pending_screenshot : Option<std::path::PathBuf>,
+    framebuffer_resized : bool,
+    scene : Option<scene::Scene>,
+    primitives : Vec<GpuPrimitive>,
+    draws : Vec<DrawCommand>,
     uniform_buffers : Vec<vk::Buffer>,
-    uniform_buffers_memory : Vec<vk::DeviceMemory>,
-    start_time : Option<std::time::SystemTime>
+    uniform_buffers_allocations : Vec<Allocation>,
+    camera : camera::Camera,
+    /// GLFW time of the previous frame, for frame time scaled camera movement.
+    last_frame_time : Option<f64>
 }
 
 impl HelloTriangleApplication {
-    pub fn run(&mut self) {
-        self.init_window();
-        self.init_vulkan();
-        self.main_loop();
+    pub fn run(&mut self) -> Result<(), RendererError> {
+        if self.options.headless {
+            self.init_vulkan()?;
+            return self.headless_loop();
+        }
+
+        self.init_window()?;
+        self.init_vulkan()?;
+        self.main_loop()
     }
 
-    fn new() -> HelloTriangleApplication {
-        HelloTriangleApplication {
-            glfw : glfw::init(glfw::FAIL_ON_ERRORS).unwrap(),
+    fn new(options : AppOptions) -> Result<HelloTriangleApplication, RendererError> {
+        let glfw = match options.headless {
+            true => None,
+            false => Some(
+                glfw::init(glfw::LOG_ERRORS)
+                .map_err(|error| RendererError::WindowSystem(format!("failed to initialize GLFW: {:?}", error)))?
+            )
+        };
+
+        let vk_entry = unsafe{ ash::Entry::new() }.map_err(RendererError::LoaderMissing)?;
+
+        Ok(HelloTriangleApplication {
+            options : options,
+            glfw : glfw,
             window : None,
             event : None,
             width : 800,
             height : 600,
-            vk_entry : unsafe{ash::Entry::new().unwrap()},
+            vk_entry : vk_entry,
             instance : None,
+            enabled_instance_extensions : Vec::new(),
+            debug_utils : None,
+            debug_messenger : None,
             physical_device : None,
             device : None,
             graphics_queue : None,
@@ -125,107 +476,237 @@ impl HelloTriangleApplication {
             swap_chain : None,
             swap_chain_images : None,
             swap_chain_image_format : None,
+            swap_chain_image_usage : vk::ImageUsageFlags::empty(),
             swap_chain_extent : None,
             swap_chain_image_views : Vec::new(),
+            offscreen_image_allocation : None,
+            depth_image : None,
+            depth_image_allocation : None,
+            depth_image_view : None,
+            textures : Vec::new(),
+            material_textures : Vec::new(),
+            texture_sampler : None,
             render_pass : None,
             descriptor_set_layout : None,
+            pipeline_interface : None,
+            descriptor_pool : None,
+            descriptor_sets : Vec::new(),
+            pipeline_cache : None,
             pipeline_layout : None,
-            graphics_pipeline : None,
+            pipelines : None,
+            shader_watcher : None,
+            wireframe : false,
             swap_chain_frame_buffers : Vec::new(),
+            allocator : None,
             command_pool : None,
+            upload_context : None,
+            unified_memory : false,
             command_buffers : None,
+            frame_command_pools : Vec::new(),
+            frame_command_buffers : Vec::new(),
             image_available_semaphores : Vec::new(),
             render_finished_semaphores : Vec::new(),
             in_flight_fences : Vec::new(),
             images_in_flight : Vec::new(),
             current_frame : 0,
-            vertices : vec![
-                Vertex{pos: [-0.5, -0.5], color: [1.0, 0.0, 0.0]},
-                Vertex{pos: [0.5, -0.5], color: [0.0, 1.0, 0.0]},
-                Vertex{pos: [0.5, 0.5], color: [0.0, 0.0, 1.0]},
-                Vertex{pos: [-0.5, 0.5], color: [1.0, 1.0, 1.0]},
-            ],
-            indices : vec![0, 1, 2, 2, 3, 0],
-            vertex_buffer : None,
-            vertex_buffer_memory : None,
-            index_buffer : None,
-            index_buffer_memory : None,
+            pending_screenshot : None,
+            framebuffer_resized : false,
+            scene : None,
+            primitives : Vec::new(),
+            draws : Vec::new(),
             uniform_buffers : Vec::new(),
-            uniform_buffers_memory : Vec::new(),
-            start_time : None
-        }
+            uniform_buffers_allocations : Vec::new(),
+            camera : camera::Camera::look_at(cgmath::Point3::new(2.0, 2.0, 2.0), cgmath::Point3::new(0.0, 0.0, 0.0)),
+            last_frame_time : None
+        })
     }
 
-    fn init_window(&mut self){
-        self.glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::NoApi));
-        self.glfw.window_hint(glfw::WindowHint::Resizable(false));
-        let window = self.glfw.create_window(self.width, self.height, "Vulkan", glfw::WindowMode::Windowed).unwrap();
+    fn init_window(&mut self) -> Result<(), RendererError> {
+        let glfw = self.glfw.as_mut().unwrap();
+        glfw.window_hint(glfw::WindowHint::ClientApi(glfw::ClientApiHint::NoApi));
+        glfw.window_hint(glfw::WindowHint::Resizable(true));
+        let mut window = glfw.create_window(self.width, self.height, "Vulkan", glfw::WindowMode::Windowed)
+            .ok_or_else(|| RendererError::WindowSystem("failed to create window!".to_owned()))?;
+        window.0.set_key_polling(true);
+        window.0.set_framebuffer_size_polling(true);
+        window.0.set_mouse_button_polling(true);
+        window.0.set_cursor_pos_polling(true);
+        window.0.set_scroll_polling(true);
+        window.0.set_focus_polling(true);
         self.window = Option::Some(window.0);
         self.event = Option::Some(window.1);
+        Ok(())
     }
 
-    fn init_vulkan(&mut self){
-        self.create_instance();
-        self.create_surface();
-        self.pick_physical_device();
-        self.create_logical_device();
-        self.create_swap_chain();
-        self.create_image_views();
-        self.create_render_pass();
-        self.create_descriptor_set_layout();
-        self.create_graphics_pipeline();
-        self.create_framebuffers();
-        self.create_command_pool();
-        self.create_vertex_buffer();
-        self.create_index_buffer();
-        self.create_uniform_buffers();
-        self.create_command_buffers();
-        self.create_sync_objects();
+    fn init_vulkan(&mut self) -> Result<(), RendererError> {
+        self.create_instance()?;
+        if !self.options.headless {
+            self.create_surface()?;
+        }
+        self.pick_physical_device()?;
+        self.create_logical_device()?;
+        self.create_allocator();
+        self.pipeline_cache = Some(pipeline_cache::PipelineCache::new(
+            self.instance.as_ref().unwrap(),
+            *self.physical_device.as_ref().unwrap(),
+            self.device.as_ref().unwrap()
+        )?);
+        if self.options.headless {
+            self.create_offscreen_target()?;
+        } else {
+            self.create_swap_chain(vk::SwapchainKHR::null())?;
+        }
+        self.create_image_views()?;
+        self.create_render_pass()?;
+        self.create_descriptor_set_layout()?;
+        self.create_graphics_pipeline()?;
+        if !self.options.headless {
+            self.shader_watcher = Some(shader_watcher::ShaderWatcher::new(std::path::Path::new(SHADER_DIRECTORY), SHADER_EXTENSIONS));
+        }
+        self.create_depth_resources()?;
+        self.create_framebuffers()?;
+        self.create_command_pool()?;
+        self.create_upload_context()?;
+        self.load_scene()?;
+        self.create_texture_images()?;
+        self.create_texture_sampler()?;
+        self.create_primitive_buffers()?;
+        // every texture and primitive goes to the GPU in a single submission
+        self.upload_context.as_mut().unwrap().flush(self.allocator.as_ref().unwrap())?;
+        log::info!("{}", self.allocator.as_ref().unwrap().stats());
+        self.create_uniform_buffers()?;
+        self.create_descriptor_pool()?;
+        self.create_descriptor_sets()?;
+        if self.options.prerecord_commands {
+            self.create_command_buffers()?;
+        } else {
+            self.create_frame_command_pools()?;
+        }
+        self.create_sync_objects()
     }
 
+    /// Destroys everything that depends on the swap chain. Safe to call on a partially initialized renderer.
     fn clean_swap_chain(&mut self) {
+        let device_ref = match self.device.as_ref() {
+            Some(device) => device,
+            None => return
+        };
+
         unsafe{
-            let device_ref = self.device.as_ref().unwrap();
             for swap_chain_frame_buffer in self.swap_chain_frame_buffers.drain(..) {
                 device_ref.destroy_framebuffer(swap_chain_frame_buffer, None);
             }
 
-            device_ref.free_command_buffers(
-                *self.command_pool.as_ref().unwrap(), 
-                self.command_buffers.as_ref().unwrap().as_slice());
-            self.command_buffers = None;
-
-            device_ref.destroy_pipeline(self.graphics_pipeline.unwrap(), None);
-            self.graphics_pipeline = None;
-
-            device_ref.destroy_pipeline_layout(*self.pipeline_layout.as_ref().unwrap(), None);
-            self.graphics_pipeline = None;
+            if let (Some(command_pool), Some(command_buffers)) = (self.command_pool, self.command_buffers.take()) {
+                device_ref.free_command_buffers(command_pool, command_buffers.as_slice());
+            }
 
-            device_ref.destroy_render_pass(*self.render_pass.as_ref().unwrap(), None);
-            self.render_pass = None;
+            if let Some(render_pass) = self.render_pass.take() {
+                device_ref.destroy_render_pass(render_pass, None);
+            }
 
             for image_view in self.swap_chain_image_views.drain(..){
                 device_ref.destroy_image_view(image_view, None);
             }
 
-            let swap_chain = ash::extensions::khr::Swapchain::new(
-                self.instance.as_ref().unwrap(),
-                device_ref
-            );
-            swap_chain.destroy_swapchain(*self.swap_chain.as_ref().unwrap(), None);
-            self.swap_chain = None;
+            if let Some(depth_image_view) = self.depth_image_view.take() {
+                device_ref.destroy_image_view(depth_image_view, None);
+            }
+            if let Some(depth_image) = self.depth_image.take() {
+                device_ref.destroy_image(depth_image, None);
+            }
+            if let Some(depth_image_allocation) = self.depth_image_allocation.take() {
+                self.allocator.as_ref().unwrap().free(depth_image_allocation);
+            }
+
+            // recreate_swap_chain takes the swap chain out beforehand so it can be passed as old_swapchain
+            if let Some(swap_chain_handle) = self.swap_chain.take() {
+                let swap_chain = ash::extensions::khr::Swapchain::new(
+                    self.instance.as_ref().unwrap(),
+                    device_ref
+                );
+                swap_chain.destroy_swapchain(swap_chain_handle, None);
+            }
 
-            for buffer in self.uniform_buffers.drain(..) {
-                device_ref.destroy_buffer(buffer, None);
+            // headless mode owns its color target instead of borrowing it from a swap chain
+            if self.options.headless {
+                for image in self.swap_chain_images.take().unwrap_or_default() {
+                    device_ref.destroy_image(image, None);
+                }
+                if let Some(allocation) = self.offscreen_image_allocation.take() {
+                    self.allocator.as_ref().unwrap().free(allocation);
+                }
+            }
+            self.swap_chain_images = None;
+        }
+    }
+
+    /// Destroys the pipelines and their layout. Called on shutdown, they survive swap chain recreation.
+    fn destroy_pipelines(&mut self) {
+        let device_ref = match self.device.as_ref() {
+            Some(device) => device,
+            None => return
+        };
+
+        unsafe{
+            if let Some(pipelines) = self.pipelines.take() {
+                pipelines.destroy(device_ref);
             }
 
-            for buffer_memory in self.uniform_buffers_memory.drain(..) {
-                device_ref.free_memory(buffer_memory, None);
+            if let Some(pipeline_layout) = self.pipeline_layout.take() {
+                device_ref.destroy_pipeline_layout(pipeline_layout, None);
             }
         }
     }
 
-    fn create_instance(&mut self){
+    fn recreate_swap_chain(&mut self) -> Result<(), RendererError> {
+        // a minimized window has a 0x0 framebuffer, which is not a valid swap chain extent
+        let mut framebuffer_size = self.window.as_ref().unwrap().get_framebuffer_size();
+        while framebuffer_size.0 == 0 || framebuffer_size.1 == 0 {
+            self.glfw.as_mut().unwrap().wait_events();
+            framebuffer_size = self.window.as_ref().unwrap().get_framebuffer_size();
+        }
+
+        unsafe{
+            self.device.as_ref().unwrap()
+            .device_wait_idle()
+            .context("failed to wait for device idle")?;
+        }
+
+        let old_swap_chain = self.swap_chain.take();
+        let old_format = self.swap_chain_image_format;
+        self.clean_swap_chain();
+
+        let swap_chain_result = self.create_swap_chain(old_swap_chain.unwrap_or(vk::SwapchainKHR::null()));
+        if let Some(old_swap_chain) = old_swap_chain {
+            let swap_chain = ash::extensions::khr::Swapchain::new(
+                self.instance.as_ref().unwrap(),
+                self.device.as_ref().unwrap()
+            );
+            unsafe{ swap_chain.destroy_swapchain(old_swap_chain, None); }
+        }
+        swap_chain_result?;
+
+        self.create_image_views()?;
+        self.create_render_pass()?;
+        // viewport and scissor are dynamic, and a render pass with the same formats stays compatible
+        if self.swap_chain_image_format != old_format {
+            self.destroy_pipelines();
+            self.create_graphics_pipeline()?;
+        }
+        self.create_depth_resources()?;
+        self.create_framebuffers()?;
+        if self.options.prerecord_commands {
+            self.create_command_buffers()?;
+        }
+
+        self.images_in_flight.clear();
+        self.images_in_flight.resize(self.swap_chain_images.as_ref().unwrap().len(), vk::Fence::null());
+        self.framebuffer_resized = false;
+        Ok(())
+    }
+
+    fn create_instance(&mut self) -> Result<(), RendererError> {
         let name = CString::new("Hello Triangle").unwrap();
         let engine_name = CString::new("No Engine").unwrap();
         let app_info = vk::ApplicationInfo {
@@ -238,26 +719,114 @@ impl HelloTriangleApplication {
             api_version : vk::API_VERSION_1_0
         };
 
-        let glfw_extensions = self.glfw.get_required_instance_extensions().unwrap();
+        let mut glfw_extensions = match self.glfw.as_ref() {
+            Some(glfw) => glfw.get_required_instance_extensions()
+                .ok_or_else(|| RendererError::WindowSystem("Vulkan is not supported by GLFW".to_owned()))?,
+            // nothing to present to, so no surface extensions are needed at all
+            None => Vec::new()
+        };
+
+        // GLFW only reports the extensions its own surface path needs, so make sure the
+        // native fallback used by create_surface is enabled as well when the loader has it.
+        let available_extensions = self.vk_entry.enumerate_instance_extension_properties()
+            .context("failed to enumerate instance extensions")?;
+        let platform_extension = platform_surface_extension_name()
+            .filter(|name| has_extension(&available_extensions, name))
+            .map(|name| name.to_str().unwrap().to_owned());
+        if let Some(platform_extension) = platform_extension {
+            if self.glfw.is_some() && !glfw_extensions.contains(&platform_extension) {
+                glfw_extensions.push(platform_extension);
+            }
+        }
+
+        let mut enabled_layers : Vec<CString> = Vec::new();
+        let debug_utils_available = has_extension(&available_extensions, ash::extensions::ext::DebugUtils::name());
+        if self.options.validation {
+            let available_layers = self.vk_entry.enumerate_instance_layer_properties()
+                .context("failed to enumerate instance layers")?;
+            let validation_layer = CString::new("VK_LAYER_KHRONOS_validation").unwrap();
+            if has_layer(&available_layers, &validation_layer) {
+                enabled_layers.push(validation_layer);
+            } else {
+                log::warn!("validation layers requested, but VK_LAYER_KHRONOS_validation is not available");
+            }
+
+            if debug_utils_available {
+                glfw_extensions.push(ash::extensions::ext::DebugUtils::name().to_str().unwrap().to_owned());
+            } else {
+                log::warn!("validation layers requested, but VK_EXT_debug_utils is not available");
+            }
+        }
+        self.enabled_instance_extensions = glfw_extensions.clone();
+
         let glfw_extensions_cstring : Vec<CString> = glfw_extensions.into_iter().map(|x| CString::new(x).unwrap()).collect();
         let glfw_extension_vec_char : Vec<*const c_char> = glfw_extensions_cstring.iter().map(|x| x.as_ptr()).collect();
+        let layer_vec_char : Vec<*const c_char> = enabled_layers.iter().map(|x| x.as_ptr()).collect();
+
+        // chaining the messenger info into the instance create info also reports
+        // problems with vkCreateInstance and vkDestroyInstance themselves
+        let debug_create_info = populate_debug_messenger_create_info();
+        let use_debug_utils = self.options.validation && debug_utils_available;
 
         let create_info = vk::InstanceCreateInfo{
             s_type : vk::StructureType::INSTANCE_CREATE_INFO,
-            p_next : std::ptr::null(),
+            p_next : match use_debug_utils {
+                true => &debug_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT as *const std::ffi::c_void,
+                false => std::ptr::null()
+            },
             flags : vk::InstanceCreateFlags::empty(),
             p_application_info : &app_info,
-            pp_enabled_layer_names : std::ptr::null(),
-            enabled_layer_count : 0,
+            pp_enabled_layer_names : layer_vec_char.as_ptr(),
+            enabled_layer_count : layer_vec_char.len() as u32,
             enabled_extension_count : glfw_extension_vec_char.len() as u32,
             pp_enabled_extension_names : glfw_extension_vec_char.as_ptr()
         };
 
-        self.instance = Option::Some(unsafe{self.vk_entry.create_instance(&create_info, None).unwrap()});
+        self.instance = Option::Some(unsafe{
+            self.vk_entry.create_instance(&create_info, None)
+            .context("failed to create instance!")?
+        });
+
+        if use_debug_utils {
+            self.setup_debug_messenger()?;
+        }
+        Ok(())
+    }
+
+    fn setup_debug_messenger(&mut self) -> Result<(), RendererError> {
+        let debug_utils = ash::extensions::ext::DebugUtils::new(&self.vk_entry, self.instance.as_ref().unwrap());
+        let create_info = populate_debug_messenger_create_info();
+
+        self.debug_messenger = Some(unsafe {
+            debug_utils.create_debug_utils_messenger(&create_info, None)
+            .context("failed to set up debug messenger!")?
+        });
+        self.debug_utils = Some(debug_utils);
+        Ok(())
     }
 
 
-    fn create_surface(&mut self) {
+    fn create_surface(&mut self) -> Result<(), RendererError> {
+        // glfwCreateWindowSurface knows which window system GLFW was built for
+        // (Win32, Xlib, XCB or Wayland), so prefer it over hand-rolled platform code.
+        let mut surface : u64 = 0;
+        let result = self.window.as_ref().unwrap().create_window_surface(
+            self.instance.as_ref().unwrap().handle().as_raw() as usize,
+            std::ptr::null(),
+            &mut surface as *mut u64
+        );
+
+        if result == vk::Result::SUCCESS.as_raw() as u32 {
+            self.surface = Option::Some(vk::SurfaceKHR::from_raw(surface));
+            return Ok(());
+        }
+
+        self.surface = Option::Some(self.create_platform_surface()?);
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn create_platform_surface(&self) -> Result<vk::SurfaceKHR, RendererError> {
         let hinstance = unsafe{ GetModuleHandleW(std::ptr::null()) as *const std::ffi::c_void };
         let hwnd = self.window.as_ref().unwrap().get_win32_window();
         let create_info = vk::Win32SurfaceCreateInfoKHR{
@@ -269,37 +838,113 @@ impl HelloTriangleApplication {
         };
 
         let surface_loader = ash::extensions::khr::Win32Surface::new(&self.vk_entry, self.instance.as_ref().unwrap());
-        self.surface = Option::Some(
-            unsafe{ surface_loader.create_win32_surface(&create_info, None).unwrap() }
-        );
+        unsafe{ surface_loader.create_win32_surface(&create_info, None).context("failed to create window surface!") }
+    }
+
+    /// Only covers Xlib, the window system GLFW is built for by default. A Wayland or XCB window has no
+    /// fallback and depends on glfwCreateWindowSurface succeeding.
+    #[cfg(target_os = "linux")]
+    fn create_platform_surface(&self) -> Result<vk::SurfaceKHR, RendererError> {
+        if !self.enabled_instance_extensions.iter().any(|x| x.as_str() == "VK_KHR_xlib_surface") {
+            return Err(RendererError::WindowSystem(
+                "failed to create window surface: the Xlib fallback needs an X11 display and VK_KHR_xlib_surface!".to_owned()
+            ));
+        }
+
+        let window_ref = self.window.as_ref().unwrap();
+        let create_info = vk::XlibSurfaceCreateInfoKHR{
+            s_type : vk::StructureType::XLIB_SURFACE_CREATE_INFO_KHR,
+            p_next : std::ptr::null(),
+            flags : Default::default(),
+            dpy : self.glfw.as_ref().unwrap().get_x11_display() as *mut vk::Display,
+            window : window_ref.get_x11_window() as vk::Window,
+        };
+
+        let surface_loader = ash::extensions::khr::XlibSurface::new(&self.vk_entry, self.instance.as_ref().unwrap());
+        unsafe{ surface_loader.create_xlib_surface(&create_info, None).context("failed to create window surface!") }
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    fn create_platform_surface(&self) -> Result<vk::SurfaceKHR, RendererError> {
+        Err(RendererError::WindowSystem("failed to create window surface!".to_owned()))
     }
 
-    fn pick_physical_device(&mut self){
+    fn pick_physical_device(&mut self) -> Result<(), RendererError> {
         let instance_ref = self.instance.as_ref().unwrap();
-        let devices = unsafe{instance_ref.enumerate_physical_devices().unwrap()};
-        for device in devices {
-            if self.is_device_suitable(&device){
-                self.physical_device = Option::Some(device);
-                break;
+        let devices = unsafe{
+            instance_ref.enumerate_physical_devices()
+            .context("failed to enumerate physical devices")?
+        };
+        let selector = self.options.gpu.as_ref().map(|x| GpuSelector::parse(x));
+
+        let mut best : Option<(DeviceScore, vk::PhysicalDevice)> = None;
+        for (index, device) in devices.into_iter().enumerate() {
+            let properties = unsafe{ instance_ref.get_physical_device_properties(device) };
+            let memory_properties = unsafe{ instance_ref.get_physical_device_memory_properties(device) };
+            let description = format!(
+                "GPU {}: {} ({:?}, uuid {})",
+                index,
+                device_name(&properties),
+                properties.device_type,
+                format_uuid(&properties.pipeline_cache_uuid)
+            );
+
+            if let Some(rejection) = self.device_rejection_reason(&device)? {
+                log::info!("{} rejected: {}", description, rejection);
+                continue;
+            }
+
+            if let Some(selector) = selector.as_ref() {
+                if !selector.matches(index, &properties) {
+                    log::info!("{} rejected: does not match --gpu/{} override", description, GPU_OVERRIDE_ENV);
+                    continue;
+                }
             }
-        }
 
-        if let None = self.physical_device {
-            panic!("failed to find GPUs with Vulkan support!");
+            let score = score_device(&properties, &memory_properties);
+            log::info!("{} candidate with score {:?}", description, score);
+
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, device));
+            }
         }
+
+        let device = match (best, self.options.gpu.as_ref()) {
+            (Some((_, device)), _) => device,
+            (None, Some(gpu)) => return Err(RendererError::InvalidArgument(
+                format!("GPU override '{}' does not match any suitable device", gpu)
+            )),
+            (None, None) => return Err(RendererError::NoSuitableDevice)
+        };
+
+        let properties = unsafe{ instance_ref.get_physical_device_properties(device) };
+        log::info!("selected GPU {}", device_name(&properties));
+        self.physical_device = Some(device);
+        Ok(())
     }
 
-    fn is_device_suitable(&self, device : &vk::PhysicalDevice) -> bool{
+    /// Returns why `device` cannot be used, or `None` if it has everything the renderer needs.
+    fn device_rejection_reason(&self, device : &vk::PhysicalDevice) -> Result<Option<&'static str>, RendererError> {
         let queue_family = self.find_queue_families(device);
-        let extension_supported = self.check_device_extension_support(device);
-        let swap_chain_adequate = match extension_supported {
-            false => false,
-            true => {
-                let swap_chain_support = self.query_swap_chain_support(device);
-                !swap_chain_support.formats.is_empty() && !swap_chain_support.present_modes.is_empty()
-            }
-        };
-        return queue_family.0.is_some() && queue_family.1.is_some() && extension_supported && swap_chain_adequate;
+        if queue_family.0.is_none() {
+            return Ok(Some("no graphics queue family"));
+        }
+        if self.options.headless {
+            return Ok(None);
+        }
+
+        if queue_family.1.is_none() {
+            return Ok(Some("no queue family can present to the window surface"));
+        }
+        if !self.check_device_extension_support(device)? {
+            return Ok(Some("missing required device extensions"));
+        }
+
+        let swap_chain_support = self.query_swap_chain_support(device)?;
+        if swap_chain_support.formats.is_empty() || swap_chain_support.present_modes.is_empty() {
+            return Ok(Some("no surface formats or present modes"));
+        }
+        Ok(None)
     }
 
     fn find_queue_families(&self, device : &vk::PhysicalDevice) -> (Option<usize>, Option<usize>) {
@@ -314,42 +959,48 @@ impl HelloTriangleApplication {
                 
             }
 
-            if let Ok(_) = unsafe{
-                surface_loader.get_physical_device_surface_support(*device, i as u32, *self.surface.as_ref().unwrap())
-            }{
-                present_family = Option::Some(i);
+            if let Some(surface) = self.surface {
+                if let Ok(true) = unsafe{
+                    surface_loader.get_physical_device_surface_support(*device, i as u32, surface)
+                }{
+                    present_family = Option::Some(i);
+                }
             }
         }
 
         return(graphics_family, present_family)
     }
 
-    fn query_swap_chain_support(&self, device : &vk::PhysicalDevice) -> SwapChainSupportDetails{
+    fn query_swap_chain_support(&self, device : &vk::PhysicalDevice) -> Result<SwapChainSupportDetails, RendererError> {
         let surface = ash::extensions::khr::Surface::new(&self.vk_entry, self.instance.as_ref().unwrap());
         let capabilities = unsafe{
-            surface.get_physical_device_surface_capabilities(*device, *self.surface.as_ref().unwrap()).unwrap()
+            surface.get_physical_device_surface_capabilities(*device, *self.surface.as_ref().unwrap())
+            .context("failed to query surface capabilities")?
         };
 
         let formats = unsafe{
-            surface.get_physical_device_surface_formats( *device, *self.surface.as_ref().unwrap()).unwrap()
+            surface.get_physical_device_surface_formats( *device, *self.surface.as_ref().unwrap())
+            .context("failed to query surface formats")?
         };
 
         let present_modes = unsafe{
-            surface.get_physical_device_surface_present_modes(*device,  *self.surface.as_ref().unwrap()).unwrap()
+            surface.get_physical_device_surface_present_modes(*device,  *self.surface.as_ref().unwrap())
+            .context("failed to query surface present modes")?
         };
 
-        return SwapChainSupportDetails{
+        return Ok(SwapChainSupportDetails{
             capabilities : capabilities,
             formats : formats,
             present_modes : present_modes
-        };
+        });
     }
 
-    fn check_device_extension_support(&self, device : &vk::PhysicalDevice) -> bool {
+    fn check_device_extension_support(&self, device : &vk::PhysicalDevice) -> Result<bool, RendererError> {
         let instance_ref = self.instance.as_ref().unwrap();
         let device_extensions = std::ffi::CString::new("VK_KHR_swapchain").unwrap();
         let available_extensions = unsafe {
-            instance_ref.enumerate_device_extension_properties(*device).unwrap()
+            instance_ref.enumerate_device_extension_properties(*device)
+            .context("failed to enumerate device extensions")?
         };
         for extension in available_extensions {
             let extension_name_null_pos = extension.extension_name.iter().position(|&x| x == 0);
@@ -361,19 +1012,24 @@ impl HelloTriangleApplication {
             
             let extension_name = CString::new(extension_name).unwrap();
             if device_extensions == extension_name{
-                return true;
+                return Ok(true);
             }
         }
-        return false;
+        return Ok(false);
     }
 
 
 
-    fn create_logical_device(&mut self){
+    fn create_logical_device(&mut self) -> Result<(), RendererError> {
         let instance_ref = self.instance.as_ref().unwrap();
         let physical_device_ref = self.physical_device.as_ref().unwrap();
         let indices = self.find_queue_families(physical_device_ref);
-        let unique_queue_families = [indices.clone().0.unwrap(), indices.clone().1.unwrap()];
+        let mut unique_queue_families = vec![indices.0.unwrap()];
+        if let Some(present_family) = indices.1 {
+            if present_family != indices.0.unwrap() {
+                unique_queue_families.push(present_family);
+            }
+        }
 
         let queue_priority : f32 = 1.0;
         let mut queue_create_infos : Vec<vk::DeviceQueueCreateInfo> = Vec::new();
@@ -390,9 +1046,17 @@ impl HelloTriangleApplication {
             queue_create_infos.push(queue_create_info);
         }
 
-        let device_features = vk::PhysicalDeviceFeatures::default();
+        let supported_features = unsafe{ instance_ref.get_physical_device_features(*physical_device_ref) };
+        let device_features = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy : supported_features.sampler_anisotropy,
+            fill_mode_non_solid : supported_features.fill_mode_non_solid,
+            ..Default::default()
+        };
 
-        let swapchain_extensions_cstring : Vec<CString> = vec![CString::new("VK_KHR_swapchain").unwrap()];
+        let swapchain_extensions_cstring : Vec<CString> = match self.options.headless {
+            true => Vec::new(),
+            false => vec![CString::new("VK_KHR_swapchain").unwrap()]
+        };
         let swapchain_extension_vec_char : Vec<*const c_char> = swapchain_extensions_cstring.iter().map(|x| x.as_ptr()).collect();
 
         let create_info = vk::DeviceCreateInfo {
@@ -402,7 +1066,7 @@ impl HelloTriangleApplication {
             p_queue_create_infos : queue_create_infos.as_ptr(),
             queue_create_info_count : queue_create_infos.len() as u32,
             p_enabled_features : &device_features,
-            enabled_extension_count : 1,
+            enabled_extension_count : swapchain_extension_vec_char.len() as u32,
             pp_enabled_extension_names : swapchain_extension_vec_char.as_ptr(),
             enabled_layer_count : 0,
             pp_enabled_layer_names : std::ptr::null()
@@ -410,16 +1074,18 @@ impl HelloTriangleApplication {
 
         self.device = Option::Some(
             unsafe{
-                instance_ref.create_device(*physical_device_ref, &create_info, None).unwrap()
+                instance_ref.create_device(*physical_device_ref, &create_info, None)
+                .context("failed to create logical device!")?
             }
         );
 
         self.graphics_queue = Option::Some(unsafe{
             self.device.as_ref().unwrap().get_device_queue(indices.0.unwrap() as u32, 0)
         });
-        self.present_queue = Option::Some(unsafe{
-            self.device.as_ref().unwrap().get_device_queue(indices.1.unwrap() as u32, 0)
+        self.present_queue = indices.1.map(|present_family| unsafe{
+            self.device.as_ref().unwrap().get_device_queue(present_family as u32, 0)
         });
+        Ok(())
     }
 
     fn choose_swap_surface_format(&self, available_formats : &Vec<vk::SurfaceFormatKHR>) -> vk::SurfaceFormatKHR{
@@ -453,8 +1119,8 @@ impl HelloTriangleApplication {
         }
     }
 
-    fn create_swap_chain(&mut self){
-        let swap_chain_support = self.query_swap_chain_support(self.physical_device.as_ref().unwrap());
+    fn create_swap_chain(&mut self, old_swap_chain : vk::SwapchainKHR) -> Result<(), RendererError> {
+        let swap_chain_support = self.query_swap_chain_support(self.physical_device.as_ref().unwrap())?;
         let surface_format = self.choose_swap_surface_format(&swap_chain_support.formats);
         let present_mode = self.choose_swap_present_mode(&swap_chain_support.present_modes);
         let extent = self.choose_swap_extent(&swap_chain_support.capabilities);
@@ -464,6 +1130,12 @@ impl HelloTriangleApplication {
             image_count = swap_chain_support.capabilities.max_image_count;
         }
 
+        // screenshots copy straight out of the swap chain images when the surface allows it
+        let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if swap_chain_support.capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
         let indices = self.find_queue_families(self.physical_device.as_ref().unwrap());
         let queue_family_indices = [indices.0.unwrap(), indices.1.unwrap()];
 
@@ -490,7 +1162,7 @@ impl HelloTriangleApplication {
             image_color_space : surface_format.color_space,
             image_extent : extent,
             image_array_layers : 1,
-            image_usage : vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            image_usage : image_usage,
             image_sharing_mode : image_sharing_mode,
             queue_family_index_count : queue_family_index_count,
             p_queue_family_indices : p_queue_familiy_indices,
@@ -498,7 +1170,7 @@ impl HelloTriangleApplication {
             composite_alpha : vk::CompositeAlphaFlagsKHR::OPAQUE,
             present_mode : present_mode,
             clipped : 1,
-            old_swapchain : vk::SwapchainKHR::null(),
+            old_swapchain : old_swap_chain,
             flags : vk::SwapchainCreateFlagsKHR::empty()
         };
 
@@ -509,58 +1181,106 @@ impl HelloTriangleApplication {
 
         self.swap_chain = Some(unsafe {
             swap_chain.create_swapchain(&create_info, None)
-            .expect("failed to create swap chain!")
+            .context("failed to create swap chain!")?
         });
 
         self.swap_chain_images = Some(unsafe{
-            swap_chain.get_swapchain_images(*self.swap_chain.as_ref().unwrap()).unwrap()
+            swap_chain.get_swapchain_images(*self.swap_chain.as_ref().unwrap())
+            .context("failed to get swap chain images!")?
         });
 
         self.swap_chain_image_format = Some(surface_format.format);
+        self.swap_chain_image_usage = image_usage;
 
         self.swap_chain_extent = Some(extent);
+        Ok(())
     }
 
-    fn create_image_views(&mut self){
-        self.swap_chain_image_views.resize(self.swap_chain_images.as_ref().unwrap().len(), vk::ImageView::null());
-        let images_len = self.swap_chain_image_views.len();
+    fn create_offscreen_target(&mut self) -> Result<(), RendererError> {
+        let candidates = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB, vk::Format::R8G8B8A8_UNORM];
+        let format = self.find_supported_format(
+            &candidates,
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT
+        )?;
+
+        let extent = vk::Extent2D {
+            width : self.width,
+            height : self.height
+        };
 
-        
-        for idx in 0 .. images_len {
-            let component_mapping = vk::ComponentMapping {
-                r : vk::ComponentSwizzle::IDENTITY,
-                g : vk::ComponentSwizzle::IDENTITY,
-                b : vk::ComponentSwizzle::IDENTITY,
-                a : vk::ComponentSwizzle::IDENTITY,
-            };
+        let image = self.create_image(
+            extent,
+            1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+        )?;
+
+        self.swap_chain_images = Some(vec![image.0]);
+        self.offscreen_image_allocation = Some(image.1);
+        self.swap_chain_image_format = Some(format);
+        self.swap_chain_image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC;
+        self.swap_chain_extent = Some(extent);
+        Ok(())
+    }
 
-            let subresource_range = vk::ImageSubresourceRange {
-                aspect_mask : vk::ImageAspectFlags::COLOR,
-                base_mip_level : 0,
-                level_count : 1,
-                base_array_layer : 0,
-                layer_count : 1
-            };
+    fn create_image_view(
+        &self,
+        image : vk::Image,
+        format : vk::Format,
+        aspect_flags : vk::ImageAspectFlags,
+        mip_levels : u32
+    ) -> Result<vk::ImageView, RendererError> {
+        let component_mapping = vk::ComponentMapping {
+            r : vk::ComponentSwizzle::IDENTITY,
+            g : vk::ComponentSwizzle::IDENTITY,
+            b : vk::ComponentSwizzle::IDENTITY,
+            a : vk::ComponentSwizzle::IDENTITY,
+        };
 
-            let create_info = vk::ImageViewCreateInfo{
-                s_type : vk::StructureType::IMAGE_VIEW_CREATE_INFO,
-                p_next : std::ptr::null(),
-                image : self.swap_chain_images.as_ref().unwrap()[idx],
-                view_type : vk::ImageViewType::TYPE_2D,
-                format : *self.swap_chain_image_format.as_ref().unwrap(),
-                components : component_mapping,
-                subresource_range : subresource_range,
-                flags : vk::ImageViewCreateFlags::empty(),
-            };
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask : aspect_flags,
+            base_mip_level : 0,
+            level_count : mip_levels,
+            base_array_layer : 0,
+            layer_count : 1
+        };
 
-            self.swap_chain_image_views[idx] = unsafe{
-                self.device.as_ref().unwrap().create_image_view(&create_info, None)
-                .expect("failed to create image views!")
-            };
+        let create_info = vk::ImageViewCreateInfo{
+            s_type : vk::StructureType::IMAGE_VIEW_CREATE_INFO,
+            p_next : std::ptr::null(),
+            image : image,
+            view_type : vk::ImageViewType::TYPE_2D,
+            format : format,
+            components : component_mapping,
+            subresource_range : subresource_range,
+            flags : vk::ImageViewCreateFlags::empty(),
+        };
+
+        unsafe{
+            self.device.as_ref().unwrap().create_image_view(&create_info, None)
+            .context("failed to create image views!")
         }
     }
 
-    fn create_render_pass (&mut self){
+    fn create_image_views(&mut self) -> Result<(), RendererError> {
+        self.swap_chain_image_views.resize(self.swap_chain_images.as_ref().unwrap().len(), vk::ImageView::null());
+        let images_len = self.swap_chain_image_views.len();
+
+        for idx in 0 .. images_len {
+            self.swap_chain_image_views[idx] = self.create_image_view(
+                self.swap_chain_images.as_ref().unwrap()[idx],
+                *self.swap_chain_image_format.as_ref().unwrap(),
+                vk::ImageAspectFlags::COLOR,
+                1
+            )?;
+        }
+        Ok(())
+    }
+
+    fn create_render_pass (&mut self) -> Result<(), RendererError> {
         let color_attachment = vk::AttachmentDescription {
             format : *self.swap_chain_image_format.as_ref().unwrap(),
             samples : vk::SampleCountFlags::TYPE_1,
@@ -569,7 +1289,22 @@ impl HelloTriangleApplication {
             stencil_load_op : vk::AttachmentLoadOp::DONT_CARE,
             stencil_store_op : vk::AttachmentStoreOp::DONT_CARE,
             initial_layout : vk::ImageLayout::UNDEFINED,
-            final_layout : vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout : match self.options.headless {
+                true => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                false => vk::ImageLayout::PRESENT_SRC_KHR
+            },
+            flags : vk::AttachmentDescriptionFlags::empty()
+        };
+
+        let depth_attachment = vk::AttachmentDescription {
+            format : self.find_depth_format()?,
+            samples : vk::SampleCountFlags::TYPE_1,
+            load_op : vk::AttachmentLoadOp::CLEAR,
+            store_op : vk::AttachmentStoreOp::DONT_CARE,
+            stencil_load_op : vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op : vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout : vk::ImageLayout::UNDEFINED,
+            final_layout : vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
             flags : vk::AttachmentDescriptionFlags::empty()
         };
 
@@ -578,6 +1313,11 @@ impl HelloTriangleApplication {
             layout : vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
         };
 
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment : 1,
+            layout : vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        };
+
         let subpass = vk::SubpassDescription {
             pipeline_bind_point : vk::PipelineBindPoint::GRAPHICS,
             color_attachment_count : 1,
@@ -585,7 +1325,7 @@ impl HelloTriangleApplication {
             input_attachment_count : 0,
             p_input_attachments : std::ptr::null(),
             p_resolve_attachments : std::ptr::null(),
-            p_depth_stencil_attachment : std::ptr::null(),
+            p_depth_stencil_attachment : &depth_attachment_ref as *const vk::AttachmentReference,
             preserve_attachment_count : 0,
             p_preserve_attachments : std::ptr::null(),
             flags : vk::SubpassDescriptionFlags::empty()
@@ -594,18 +1334,21 @@ impl HelloTriangleApplication {
         let dependency = vk::SubpassDependency {
             src_subpass : vk::SUBPASS_EXTERNAL,
             dst_subpass : 0,
-            src_stage_mask : vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            src_access_mask : vk::AccessFlags::empty(),
-            dst_stage_mask : vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            dst_access_mask : vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            // the single depth image is shared by every frame, so the previous frame's depth writes must finish first
+            src_stage_mask : vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            src_access_mask : vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_stage_mask : vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            dst_access_mask : vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
             dependency_flags : vk::DependencyFlags::empty()
         };
 
+        let attachments = [color_attachment, depth_attachment];
+
         let render_pass_info = vk::RenderPassCreateInfo {
             s_type : vk::StructureType::RENDER_PASS_CREATE_INFO,
             p_next : std::ptr::null(),
-            attachment_count : 1,
-            p_attachments : &color_attachment as *const vk::AttachmentDescription,
+            attachment_count : attachments.len() as u32,
+            p_attachments : attachments.as_ptr(),
             subpass_count : 1,
             p_subpasses : &subpass as *const vk::SubpassDescription,
             flags : vk::RenderPassCreateFlags::empty(),
@@ -616,38 +1359,83 @@ impl HelloTriangleApplication {
         self.render_pass = Some(unsafe {
             self.device.as_ref().unwrap()
             .create_render_pass(&render_pass_info, None)
-            .expect("failed to create render pass")
+            .context("failed to create render pass")?
         });
+        Ok(())
+    }
+
+    fn find_supported_format(
+        &self,
+        candidates : &[vk::Format],
+        tiling : vk::ImageTiling,
+        features : vk::FormatFeatureFlags
+    ) -> Result<vk::Format, RendererError> {
+        candidates.iter().copied().find(|&format| {
+            let properties = unsafe {
+                self.instance.as_ref().unwrap()
+                .get_physical_device_format_properties(*self.physical_device.as_ref().unwrap(), format)
+            };
+            match tiling {
+                vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+                _ => properties.optimal_tiling_features.contains(features)
+            }
+        }).ok_or(RendererError::Vulkan {
+            context : "failed to find supported format!",
+            result : vk::Result::ERROR_FORMAT_NOT_SUPPORTED
+        })
     }
 
-    fn create_shader_module(&self, code : Vec<u8>) -> vk::ShaderModule{
+    fn find_depth_format(&self) -> Result<vk::Format, RendererError> {
+        self.find_supported_format(
+            &[vk::Format::D32_SFLOAT, vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT],
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+        )
+    }
+
+    /// Creates the depth buffer sized to the current swap chain extent.
+    fn create_depth_resources(&mut self) -> Result<(), RendererError> {
+        let depth_format = self.find_depth_format()?;
+
+        let (image, image_allocation) = self.create_image(
+            *self.swap_chain_extent.as_ref().unwrap(),
+            1,
+            depth_format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+        )?;
+        self.depth_image = Some(image);
+        self.depth_image_allocation = Some(image_allocation);
+
+        // the render pass moves the image out of UNDEFINED, so no explicit layout transition is needed
+        self.depth_image_view = Some(self.create_image_view(image, depth_format, vk::ImageAspectFlags::DEPTH, 1)?);
+        Ok(())
+    }
+
+    fn create_shader_module(&self, code : &[u32]) -> Result<vk::ShaderModule, RendererError> {
         let create_info = vk::ShaderModuleCreateInfo {
             s_type : vk::StructureType::SHADER_MODULE_CREATE_INFO,
             p_next : std::ptr::null(),
-            code_size : code.len(),
-            p_code : code.as_ptr() as *const u32,
+            code_size : code.len() * std::mem::size_of::<u32>(),
+            p_code : code.as_ptr(),
             flags : vk::ShaderModuleCreateFlags::empty()
         };
 
-        return unsafe{self.device.as_ref().unwrap().create_shader_module(&create_info, None).expect("failed to create shader module!")};
+        return unsafe{self.device.as_ref().unwrap().create_shader_module(&create_info, None).context("failed to create shader module!")};
     }
 
-    fn find_memory_type(&self, type_filter : u32, properties : vk::MemoryPropertyFlags) -> Result<u32, &str> {
-        let mem_properties = unsafe {
-            self.instance.as_ref().unwrap()
-            .get_physical_device_memory_properties(
-                *self.physical_device.as_ref().unwrap()
-            )
-        };
-
-        for i in 0..mem_properties.memory_type_count {
-            if ((type_filter & (1 << i)) > 0)
-            && (mem_properties.memory_types[i as usize].property_flags.contains(properties)) {
-                return Ok(i);
-            }
-        }
-
-        return Err("failed to find suitable memory type!");
+    fn create_allocator(&mut self) {
+        let instance_ref = self.instance.as_ref().unwrap();
+        let physical_device = *self.physical_device.as_ref().unwrap();
+        let memory_properties = unsafe{ instance_ref.get_physical_device_memory_properties(physical_device) };
+        let properties = unsafe{ instance_ref.get_physical_device_properties(physical_device) };
+
+        self.allocator = Some(allocator::Allocator::new(
+            self.device.as_ref().unwrap(),
+            memory_properties,
+            properties.limits.buffer_image_granularity
+        ));
     }
 
     fn create_buffer(
@@ -655,7 +1443,7 @@ impl HelloTriangleApplication {
         size : vk::DeviceSize,
         usage : vk::BufferUsageFlags,
         properties : vk::MemoryPropertyFlags,
-    ) -> (vk::Buffer, vk::DeviceMemory){
+    ) -> Result<(vk::Buffer, Allocation), RendererError> {
         let buffer_info = vk::BufferCreateInfo {
             s_type : vk::StructureType::BUFFER_CREATE_INFO,
             p_next : std::ptr::null(),
@@ -668,36 +1456,111 @@ impl HelloTriangleApplication {
         };
 
         let device_ref = self.device.as_ref().unwrap();
+        let allocator = self.allocator.as_ref().unwrap();
 
         let buffer = unsafe{
             device_ref.create_buffer(&buffer_info, None)
-            .expect("failed to create vertex buffer!")
+            .context("failed to create vertex buffer!")?
         };
 
         let mem_requirements = unsafe{
             device_ref.get_buffer_memory_requirements(buffer)
         };
 
-        let alloc_info = vk::MemoryAllocateInfo {
-            s_type : vk::StructureType::MEMORY_ALLOCATE_INFO,
-            p_next : std::ptr::null(),
-            allocation_size : mem_requirements.size,
-            memory_type_index : self.find_memory_type(mem_requirements.memory_type_bits, properties).unwrap()
+        let allocation = match allocator.allocate(&mem_requirements, properties, ResourceKind::Linear) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe{ device_ref.destroy_buffer(buffer, None); }
+                return Err(error);
+            }
         };
 
-        let buffer_memory = unsafe {
-            device_ref.allocate_memory(&alloc_info, None)
-            .expect("failed to allocate vertex buffer memory")
-        };
+        if let Err(result) = unsafe{ device_ref.bind_buffer_memory(buffer, allocation.memory, allocation.offset) } {
+            unsafe{ device_ref.destroy_buffer(buffer, None); }
+            allocator.free(allocation);
+            return Err(result).context("failed to bind buffer memory!");
+        }
 
-        unsafe{
-            device_ref.bind_buffer_memory(buffer, buffer_memory, 0);
+        Ok((buffer, allocation))
+    }
+
+    /// Releases a buffer created by `create_buffer`.
+    fn destroy_buffer(&self, buffer : (vk::Buffer, Allocation)) {
+        unsafe{ self.device.as_ref().unwrap().destroy_buffer(buffer.0, None); }
+        self.allocator.as_ref().unwrap().free(buffer.1);
+    }
+
+    fn create_image(
+        &self,
+        extent : vk::Extent2D,
+        mip_levels : u32,
+        format : vk::Format,
+        tiling : vk::ImageTiling,
+        usage : vk::ImageUsageFlags,
+        properties : vk::MemoryPropertyFlags,
+    ) -> Result<(vk::Image, Allocation), RendererError> {
+        let image_info = vk::ImageCreateInfo {
+            s_type : vk::StructureType::IMAGE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            image_type : vk::ImageType::TYPE_2D,
+            extent : vk::Extent3D {
+                width : extent.width,
+                height : extent.height,
+                depth : 1
+            },
+            mip_levels : mip_levels,
+            array_layers : 1,
+            format : format,
+            tiling : tiling,
+            initial_layout : vk::ImageLayout::UNDEFINED,
+            usage : usage,
+            sharing_mode : vk::SharingMode::EXCLUSIVE,
+            samples : vk::SampleCountFlags::TYPE_1,
+            flags : vk::ImageCreateFlags::empty(),
+            queue_family_index_count : 0,
+            p_queue_family_indices : std::ptr::null()
+        };
+
+        let device_ref = self.device.as_ref().unwrap();
+        let allocator = self.allocator.as_ref().unwrap();
+
+        let image = unsafe{
+            device_ref.create_image(&image_info, None)
+            .context("failed to create image!")?
+        };
+
+        let mem_requirements = unsafe{
+            device_ref.get_image_memory_requirements(image)
+        };
+
+        let kind = match tiling {
+            vk::ImageTiling::LINEAR => ResourceKind::Linear,
+            _ => ResourceKind::Optimal
+        };
+        let allocation = match allocator.allocate(&mem_requirements, properties, kind) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe{ device_ref.destroy_image(image, None); }
+                return Err(error);
+            }
+        };
+
+        if let Err(result) = unsafe{ device_ref.bind_image_memory(image, allocation.memory, allocation.offset) } {
+            unsafe{ device_ref.destroy_image(image, None); }
+            allocator.free(allocation);
+            return Err(result).context("failed to bind image memory!");
         }
 
-        (buffer, buffer_memory)
+        Ok((image, allocation))
     }
 
-    fn copy_buffer(&self, src_buffer : &vk::Buffer, dst_buffer : &vk::Buffer, size : vk::DeviceSize){
+    /// Releases an image created by `create_image`.
+    fn destroy_image(&self, image : (vk::Image, Allocation)) {
+        unsafe{ self.device.as_ref().unwrap().destroy_image(image.0, None); }
+        self.allocator.as_ref().unwrap().free(image.1);
+    }
+
+    fn begin_single_time_commands(&self) -> Result<vk::CommandBuffer, RendererError> {
         let device_ref = self.device.as_ref().unwrap();
         let alloc_info = vk::CommandBufferAllocateInfo {
             s_type : vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
@@ -709,7 +1572,7 @@ impl HelloTriangleApplication {
 
         let command_buffer = unsafe{
             device_ref.allocate_command_buffers(&alloc_info)
-            .unwrap()
+            .context("failed to allocate command buffers!")?[0]
         };
 
         let begin_info = vk::CommandBufferBeginInfo {
@@ -719,17 +1582,23 @@ impl HelloTriangleApplication {
             p_inheritance_info : std::ptr::null()
         };
 
-        let copy_region = [vk::BufferCopy {
-            src_offset : 0,
-            dst_offset : 0,
-            size : size
-        }];
+        unsafe{
+            device_ref.begin_command_buffer(command_buffer, &begin_info)
+            .context("failed to begin recording command buffer")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    fn end_single_time_commands(&self, command_buffer : vk::CommandBuffer) -> Result<(), RendererError> {
+        let device_ref = self.device.as_ref().unwrap();
+        let command_buffers = [command_buffer];
 
         let submit_info = [vk::SubmitInfo {
             s_type : vk::StructureType::SUBMIT_INFO,
             p_next : std::ptr::null(),
             command_buffer_count : 1,
-            p_command_buffers : command_buffer.as_ptr(),
+            p_command_buffers : command_buffers.as_ptr(),
             wait_semaphore_count : 0,
             p_wait_semaphores : std::ptr::null(),
             signal_semaphore_count : 0,
@@ -737,329 +1606,1000 @@ impl HelloTriangleApplication {
             p_wait_dst_stage_mask : std::ptr::null()
         }];
 
+        let result = unsafe{
+            device_ref.end_command_buffer(command_buffer)
+            .context("failed to record command buffer")
+            .and_then(|_| {
+                device_ref.queue_submit(*self.graphics_queue.as_ref().unwrap(), &submit_info, vk::Fence::null())
+                .context("failed to submit command buffer")
+            })
+            .and_then(|_| {
+                device_ref.queue_wait_idle(*self.graphics_queue.as_ref().unwrap())
+                .context("failed to wait for queue")
+            })
+        };
+
+        // only free the command buffer once the queue is idle or the submission never happened
         unsafe{
-            device_ref.begin_command_buffer(command_buffer[0], &begin_info);
-            device_ref.cmd_copy_buffer(command_buffer[0], *src_buffer, *dst_buffer, &copy_region);
-            device_ref.end_command_buffer(command_buffer[0]);
-            device_ref.queue_submit(*self.graphics_queue.as_ref().unwrap(), &submit_info, vk::Fence::null());
-            device_ref.queue_wait_idle(*self.graphics_queue.as_ref().unwrap());
-            device_ref.free_command_buffers(*self.command_pool.as_ref().unwrap(), command_buffer.as_slice());
+            device_ref.free_command_buffers(*self.command_pool.as_ref().unwrap(), &command_buffers);
         }
+        result
     }
 
-    fn create_vertex_buffer(&mut self) {
+    fn copy_image_to_buffer(&self, image : vk::Image, layout : vk::ImageLayout, buffer : vk::Buffer, extent : vk::Extent2D) -> Result<(), RendererError> {
         let device_ref = self.device.as_ref().unwrap();
-        let buffer_size = (std::mem::size_of::<Vertex>() * self.vertices.len()) as vk::DeviceSize;
+        let command_buffer = self.begin_single_time_commands()?;
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask : vk::ImageAspectFlags::COLOR,
+            base_mip_level : 0,
+            level_count : 1,
+            base_array_layer : 0,
+            layer_count : 1
+        };
 
-        let staging_buffer = self.create_buffer(
-            buffer_size, 
-            vk::BufferUsageFlags::TRANSFER_SRC, 
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
-        );
+        let to_transfer = [vk::ImageMemoryBarrier {
+            s_type : vk::StructureType::IMAGE_MEMORY_BARRIER,
+            p_next : std::ptr::null(),
+            src_access_mask : vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask : vk::AccessFlags::TRANSFER_READ,
+            old_layout : layout,
+            new_layout : vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            image : image,
+            subresource_range : subresource_range
+        }];
+
+        let to_original = [vk::ImageMemoryBarrier {
+            s_type : vk::StructureType::IMAGE_MEMORY_BARRIER,
+            p_next : std::ptr::null(),
+            src_access_mask : vk::AccessFlags::TRANSFER_READ,
+            dst_access_mask : vk::AccessFlags::empty(),
+            old_layout : vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout : layout,
+            src_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            image : image,
+            subresource_range : subresource_range
+        }];
+
+        let region = [vk::BufferImageCopy {
+            buffer_offset : 0,
+            buffer_row_length : 0,
+            buffer_image_height : 0,
+            image_subresource : vk::ImageSubresourceLayers {
+                aspect_mask : vk::ImageAspectFlags::COLOR,
+                mip_level : 0,
+                base_array_layer : 0,
+                layer_count : 1
+            },
+            image_offset : vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent : vk::Extent3D {
+                width : extent.width,
+                height : extent.height,
+                depth : 1
+            }
+        }];
 
         unsafe{
-            let data = device_ref.map_memory(
-                staging_buffer.1,
-                0,
-                buffer_size,
-                vk::MemoryMapFlags::empty()
-            ).expect("Failed to map mamory") as *mut Vertex;
-            data.copy_from_nonoverlapping(self.vertices.as_ptr(), self.vertices.len());
-            device_ref.unmap_memory(staging_buffer.1);
+            device_ref.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer
+            );
+            device_ref.cmd_copy_image_to_buffer(command_buffer, image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &region);
+            device_ref.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_original
+            );
         }
 
-        let buffer = self.create_buffer(
-            buffer_size, 
-            vk::BufferUsageFlags::TRANSFER_DST, 
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
-        );
+        self.end_single_time_commands(command_buffer)
+    }
 
-        self.copy_buffer(&staging_buffer.0, &buffer.0, buffer_size);
+    /// Copies the given color image into host memory and returns it as tightly packed RGBA8 rows.
+    fn read_back_image(&self, image : vk::Image, layout : vk::ImageLayout) -> Result<Vec<u8>, RendererError> {
+        let extent = *self.swap_chain_extent.as_ref().unwrap();
+        let format = *self.swap_chain_image_format.as_ref().unwrap();
+        let buffer_size = extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4;
 
-        self.vertex_buffer = Some(buffer.0);
-        self.vertex_buffer_memory = Some(buffer.1);
+        let staging_buffer = self.create_buffer(
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let mut pixels = vec![0u8; buffer_size as usize];
+        let result = self.copy_image_to_buffer(image, layout, staging_buffer.0, extent).map(|_| unsafe{
+            let data = staging_buffer.1.mapped_ptr().unwrap();
+            pixels.as_mut_ptr().copy_from_nonoverlapping(data, pixels.len());
+        });
+
+        self.destroy_buffer(staging_buffer);
+        result?;
+
+        convert_to_rgba8(format, &mut pixels)?;
+        Ok(pixels)
+    }
+
+    /// Writes the rendered `image` to `path` as a PNG. Swap chain images may only be read between acquiring
+    /// and presenting them.
+    fn save_screenshot(&self, path : &std::path::Path, image : vk::Image, layout : vk::ImageLayout) -> Result<(), RendererError> {
+        if !self.swap_chain_image_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Err(RendererError::ImageWrite {
+                path : path.to_owned(),
+                message : "the surface does not support copying from swap chain images!".to_owned()
+            });
+        }
 
         unsafe{
-            device_ref.destroy_buffer(*&staging_buffer.0, None);
-            device_ref.free_memory(*&staging_buffer.1, None);
+            self.device.as_ref().unwrap()
+            .device_wait_idle()
+            .context("failed to wait for device idle")?;
         }
+
+        let pixels = self.read_back_image(image, layout)?;
+        let extent = self.swap_chain_extent.as_ref().unwrap();
+        write_png(path, extent.width, extent.height, &pixels)
     }
 
-    fn create_index_buffer(&mut self) {
-        let device_ref = self.device.as_ref().unwrap();
-        let buffer_size = (std::mem::size_of::<u16>() * self.indices.len()) as vk::DeviceSize;
+    fn transition_image_layout(
+        &self,
+        command_buffer : vk::CommandBuffer,
+        image : vk::Image,
+        mip_levels : u32,
+        old_layout : vk::ImageLayout,
+        new_layout : vk::ImageLayout
+    ) -> Result<(), RendererError> {
+        let (src_access_mask, dst_access_mask, src_stage, dst_stage) = match (old_layout, new_layout) {
+            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER
+            ),
+            (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+            ),
+            _ => return Err(RendererError::InvalidArgument(
+                format!("unsupported layout transition from {:?} to {:?}!", old_layout, new_layout)
+            ))
+        };
 
-        let staging_buffer = self.create_buffer(
-            buffer_size, 
-            vk::BufferUsageFlags::TRANSFER_SRC, 
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
-        );
+        let barrier = [vk::ImageMemoryBarrier {
+            s_type : vk::StructureType::IMAGE_MEMORY_BARRIER,
+            p_next : std::ptr::null(),
+            src_access_mask : src_access_mask,
+            dst_access_mask : dst_access_mask,
+            old_layout : old_layout,
+            new_layout : new_layout,
+            src_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            image : image,
+            subresource_range : vk::ImageSubresourceRange {
+                aspect_mask : vk::ImageAspectFlags::COLOR,
+                base_mip_level : 0,
+                level_count : mip_levels,
+                base_array_layer : 0,
+                layer_count : 1
+            }
+        }];
 
         unsafe{
-            let data = device_ref.map_memory(
-                staging_buffer.1,
-                0,
-                buffer_size,
-                vk::MemoryMapFlags::empty()
-            ).expect("Failed to map mamory") as *mut u16;
-            data.copy_from_nonoverlapping(self.indices.as_ptr(), self.indices.len());
-            device_ref.unmap_memory(staging_buffer.1);
+            self.device.as_ref().unwrap().cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barrier
+            );
         }
+        Ok(())
+    }
 
-        let buffer = self.create_buffer(
-            buffer_size, 
-            vk::BufferUsageFlags::TRANSFER_DST, 
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
-        );
+    /// Copies tightly packed mip levels out of `buffer`; `levels[i]` is the byte offset and extent of mip level `i`.
+    fn copy_buffer_to_image(&self, command_buffer : vk::CommandBuffer, buffer : vk::Buffer, image : vk::Image, levels : &[(vk::DeviceSize, vk::Extent2D)]) {
+        let regions : Vec<vk::BufferImageCopy> = levels.iter().enumerate().map(|(mip_level, (offset, extent))| {
+            vk::BufferImageCopy {
+                buffer_offset : *offset,
+                buffer_row_length : 0,
+                buffer_image_height : 0,
+                image_subresource : vk::ImageSubresourceLayers {
+                    aspect_mask : vk::ImageAspectFlags::COLOR,
+                    mip_level : mip_level as u32,
+                    base_array_layer : 0,
+                    layer_count : 1
+                },
+                image_offset : vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent : vk::Extent3D {
+                    width : extent.width,
+                    height : extent.height,
+                    depth : 1
+                }
+            }
+        }).collect();
 
-        self.copy_buffer(&staging_buffer.0, &buffer.0, buffer_size);
+        unsafe{
+            self.device.as_ref().unwrap()
+            .cmd_copy_buffer_to_image(command_buffer, buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions);
+        }
+    }
+
+    /// Fills mip levels 1.. of `image` by repeatedly blitting the previous level, leaving every level in
+    /// SHADER_READ_ONLY_OPTIMAL. Level 0 must already be uploaded and every level must be in TRANSFER_DST_OPTIMAL.
+    fn generate_mipmaps(&self, command_buffer : vk::CommandBuffer, image : vk::Image, extent : vk::Extent2D, mip_levels : u32) {
+        let device_ref = self.device.as_ref().unwrap();
+
+        let mut barrier = vk::ImageMemoryBarrier {
+            s_type : vk::StructureType::IMAGE_MEMORY_BARRIER,
+            p_next : std::ptr::null(),
+            src_access_mask : vk::AccessFlags::empty(),
+            dst_access_mask : vk::AccessFlags::empty(),
+            old_layout : vk::ImageLayout::UNDEFINED,
+            new_layout : vk::ImageLayout::UNDEFINED,
+            src_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index : vk::QUEUE_FAMILY_IGNORED,
+            image : image,
+            subresource_range : vk::ImageSubresourceRange {
+                aspect_mask : vk::ImageAspectFlags::COLOR,
+                base_mip_level : 0,
+                level_count : 1,
+                base_array_layer : 0,
+                layer_count : 1
+            }
+        };
 
-        self.index_buffer = Some(buffer.0);
-        self.index_buffer_memory = Some(buffer.1);
+        let mut mip_width = extent.width as i32;
+        let mut mip_height = extent.height as i32;
+
+        for level in 1..mip_levels {
+            barrier.subresource_range.base_mip_level = level - 1;
+            barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+            barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+            barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+            barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
+
+            let next_width = std::cmp::max(mip_width / 2, 1);
+            let next_height = std::cmp::max(mip_height / 2, 1);
+
+            let blit = [vk::ImageBlit {
+                src_offsets : [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D { x: mip_width, y: mip_height, z: 1 }
+                ],
+                src_subresource : vk::ImageSubresourceLayers {
+                    aspect_mask : vk::ImageAspectFlags::COLOR,
+                    mip_level : level - 1,
+                    base_array_layer : 0,
+                    layer_count : 1
+                },
+                dst_offsets : [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D { x: next_width, y: next_height, z: 1 }
+                ],
+                dst_subresource : vk::ImageSubresourceLayers {
+                    aspect_mask : vk::ImageAspectFlags::COLOR,
+                    mip_level : level,
+                    base_array_layer : 0,
+                    layer_count : 1
+                }
+            }];
+
+            unsafe{
+                device_ref.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier]
+                );
+
+                device_ref.cmd_blit_image(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &blit,
+                    vk::Filter::LINEAR
+                );
+            }
+
+            barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+            barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+            barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
+            barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+            unsafe{
+                device_ref.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier]
+                );
+            }
+
+            mip_width = next_width;
+            mip_height = next_height;
+        }
+
+        // the last level is only ever written to, so it still needs its own transition
+        barrier.subresource_range.base_mip_level = mip_levels - 1;
+        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
 
         unsafe{
-            device_ref.destroy_buffer(*&staging_buffer.0, None);
-            device_ref.free_memory(*&staging_buffer.1, None);
+            device_ref.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier]
+            );
         }
     }
 
-    fn create_uniform_buffers(&mut self) {
-        let buffer_size = std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize;
+    /// Records the upload of `pixels` with a full mip chain to a DEVICE_LOCAL image. The chain is blitted on the
+    /// GPU when the format supports linear blit filtering and downsampled on the CPU otherwise. The texture is
+    /// only usable once `upload_context` has been flushed.
+    fn create_texture_image(&self, upload_context : &mut upload::UploadContext, pixels : &image::RgbaImage) -> Result<Texture, RendererError> {
+        let extent = vk::Extent2D {
+            width : pixels.width(),
+            height : pixels.height()
+        };
+        let format = vk::Format::R8G8B8A8_SRGB;
+        let mip_levels = 32 - std::cmp::max(extent.width, extent.height).leading_zeros();
 
-        self.uniform_buffers.resize(
-            self.swap_chain_images.as_ref().unwrap().len(), 
-            vk::Buffer::null()
+        let format_properties = unsafe{
+            self.instance.as_ref().unwrap()
+            .get_physical_device_format_properties(*self.physical_device.as_ref().unwrap(), format)
+        };
+        let blit_supported = format_properties.optimal_tiling_features.contains(
+            vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
         );
 
-        self.uniform_buffers_memory.resize(
-            self.swap_chain_images.as_ref().unwrap().len(),
-            vk::DeviceMemory::null()
-        );
+        // with blitting only level 0 goes through the staging buffer, otherwise every level is packed back to back
+        let mut levels = vec![std::borrow::Cow::Borrowed(pixels)];
+        if !blit_supported {
+            log::info!("{:?} does not support linear blits, generating mipmaps on the CPU", format);
+            for level in 1..mip_levels {
+                let previous = &levels[level as usize - 1];
+                let downsampled = image::imageops::resize(
+                    previous.as_ref(),
+                    std::cmp::max(previous.width() / 2, 1),
+                    std::cmp::max(previous.height() / 2, 1),
+                    image::imageops::FilterType::Triangle
+                );
+                levels.push(std::borrow::Cow::Owned(downsampled));
+            }
+        }
 
-        for i in 1..self.swap_chain_images.as_ref().unwrap().len() {
-            let buffer = unsafe{
-                self.create_buffer(
-                    buffer_size, 
-                    vk::BufferUsageFlags::UNIFORM_BUFFER, 
-                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
-                )
-            };
-            self.uniform_buffers[i] = buffer.0;
-            self.uniform_buffers_memory[i] = buffer.1;
+        let (staging_buffer, level_regions) = match levels.len() {
+            1 => {
+                let (buffer, offset) = upload_context.stage(self.allocator.as_ref().unwrap(), pixels.as_raw())?;
+                (buffer, vec![(offset, extent)])
+            },
+            _ => {
+                let mut data = Vec::new();
+                let mut level_regions = Vec::with_capacity(levels.len());
+                for level in levels.iter() {
+                    level_regions.push((data.len() as vk::DeviceSize, vk::Extent2D { width : level.width(), height : level.height() }));
+                    data.extend_from_slice(level.as_raw());
+                }
+                let (buffer, offset) = upload_context.stage(self.allocator.as_ref().unwrap(), &data)?;
+                for region in level_regions.iter_mut() {
+                    region.0 += offset;
+                }
+                (buffer, level_regions)
+            }
+        };
+
+        let image = self.create_image(
+            extent,
+            mip_levels,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+        )?;
+
+        let view = upload_context.command_buffer().and_then(|command_buffer| {
+            self.transition_image_layout(command_buffer, image.0, mip_levels, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)?;
+            self.copy_buffer_to_image(command_buffer, staging_buffer, image.0, &level_regions);
+            match blit_supported {
+                true => self.generate_mipmaps(command_buffer, image.0, extent, mip_levels),
+                false => self.transition_image_layout(command_buffer, image.0, mip_levels, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)?
+            }
+            self.create_image_view(image.0, format, vk::ImageAspectFlags::COLOR, mip_levels)
+        });
+
+        match view {
+            Ok(view) => Ok(Texture {
+                image : image.0,
+                allocation : image.1,
+                view : view,
+                mip_levels : mip_levels
+            }),
+            Err(error) => {
+                self.destroy_image(image);
+                Err(error)
+            }
         }
     }
 
-    fn create_descriptor_set_layout(&mut self){
-        let ubo_layout_binding = vk::DescriptorSetLayoutBinding {
-            binding : 0,
-            descriptor_type : vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count : 1,
-            stage_flags : vk::ShaderStageFlags::VERTEX,
-            p_immutable_samplers : std::ptr::null()
-        };
+    /// Uploads every scene image plus a white fallback for materials without a base color texture.
+    fn create_texture_images(&mut self) -> Result<(), RendererError> {
+        self.with_upload_context(|app, upload_context| {
+            let scene = app.scene.as_ref().unwrap();
+            let white = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]));
+
+            for pixels in scene.images.iter().chain(std::iter::once(&white)) {
+                let texture = app.create_texture_image(upload_context, pixels)?;
+                app.textures.push(texture);
+            }
+
+            let white_texture = app.textures.len() - 1;
+            app.material_textures = scene.materials.iter()
+                .map(|material| material.base_color_texture.unwrap_or(white_texture))
+                .collect();
+            Ok(())
+        })
+    }
+
+    fn create_texture_sampler(&mut self) -> Result<(), RendererError> {
+        let instance_ref = self.instance.as_ref().unwrap();
+        let physical_device = *self.physical_device.as_ref().unwrap();
+        let supported_features = unsafe{ instance_ref.get_physical_device_features(physical_device) };
+        let properties = unsafe{ instance_ref.get_physical_device_properties(physical_device) };
+
+        // create_logical_device enables samplerAnisotropy whenever the device supports it
+        let settings = &self.options.sampler;
+        let anisotropy_enable = settings.anisotropy && supported_features.sampler_anisotropy == vk::TRUE;
 
-        let layout_info = vk::DescriptorSetLayoutCreateInfo {
-            s_type : vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+        let sampler_info = vk::SamplerCreateInfo {
+            s_type : vk::StructureType::SAMPLER_CREATE_INFO,
             p_next : std::ptr::null(),
-            binding_count : 1,
-            p_bindings : &ubo_layout_binding as *const DescriptorSetLayoutBinding,
-            flags : vk::DescriptorSetLayoutCreateFlags::empty()
+            mag_filter : settings.filter,
+            min_filter : settings.filter,
+            address_mode_u : settings.address_mode,
+            address_mode_v : settings.address_mode,
+            address_mode_w : settings.address_mode,
+            anisotropy_enable : anisotropy_enable as vk::Bool32,
+            max_anisotropy : match anisotropy_enable {
+                true => properties.limits.max_sampler_anisotropy,
+                false => 1.0
+            },
+            border_color : vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates : vk::FALSE,
+            compare_enable : vk::FALSE,
+            compare_op : vk::CompareOp::ALWAYS,
+            mipmap_mode : vk::SamplerMipmapMode::LINEAR,
+            mip_lod_bias : 0.0,
+            min_lod : 0.0,
+            max_lod : self.textures.iter().map(|texture| texture.mip_levels).max().unwrap_or(1) as f32,
+            flags : vk::SamplerCreateFlags::empty()
         };
 
-        self.descriptor_set_layout = Some(unsafe {
+        self.texture_sampler = Some(unsafe{
             self.device.as_ref().unwrap()
-            .create_descriptor_set_layout(&layout_info, None)
-            .expect("failed to create descriptor set layout!")
+            .create_sampler(&sampler_info, None)
+            .context("failed to create texture sampler!")?
         });
+        Ok(())
     }
 
+    /// Loads `options.model` (glTF/GLB by extension, OBJ otherwise) or falls back to the built-in quads,
+    /// then flattens the node tree into one draw per primitive instance.
+    fn load_scene(&mut self) -> Result<(), RendererError> {
+        let mut scene = match self.options.model.as_ref() {
+            None => {
+                let (vertices, indices) = default_geometry();
+                scene::Scene::from_geometry(vertices, indices, Some(scene::load_image(std::path::Path::new(DEFAULT_TEXTURE))?))
+            },
+            Some(path) => match path.extension().and_then(|extension| extension.to_str()) {
+                Some(extension) if extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb") => {
+                    scene::Scene::load_gltf(path)?
+                },
+                _ => scene::Scene::load_obj(path)?
+            }
+        };
 
-    fn create_graphics_pipeline(&mut self){
-        let vert_shader_code = read_file(std::path::Path::new("shaders/vert.spv"));
-        let frag_shader_code = read_file(std::path::Path::new("shaders/frag.spv"));
+        if let Some(texture) = self.options.texture.as_ref() {
+            scene.override_texture(scene::load_image(texture)?);
+        }
 
-        let vert_shader_module = self.create_shader_module(vert_shader_code);
-        let frag_shader_module = self.create_shader_module(frag_shader_code);
-        let name = std::ffi::CString::new("main").unwrap();
+        let mut primitive_offsets = Vec::with_capacity(scene.meshes.len());
+        let mut primitive_count = 0;
+        for mesh in scene.meshes.iter() {
+            primitive_offsets.push(primitive_count);
+            primitive_count += mesh.primitives.len();
+        }
 
-        let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo {
-            s_type : vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            stage : vk::ShaderStageFlags::VERTEX,
-            module : vert_shader_module,
-            p_name : name.as_ptr() as *const i8,
-            flags : PipelineShaderStageCreateFlags::empty(),
-            p_specialization_info : std::ptr::null()
+        // only nodes reachable from the roots belong to the scene
+        self.draws.clear();
+        let mut stack : Vec<usize> = scene.roots.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            if let Some(mesh) = scene.nodes[node].mesh {
+                for (primitive, scene_primitive) in scene.meshes[mesh].primitives.iter().enumerate() {
+                    self.draws.push(DrawCommand {
+                        primitive : primitive_offsets[mesh] + primitive,
+                        node : node,
+                        transparent : scene.materials[scene_primitive.material].alpha_blend
+                    });
+                }
+            }
+            stack.extend(scene.nodes[node].children.iter().rev());
+        }
+
+        log::info!(
+            "scene has {} nodes, {} primitives, {} materials and {} draws",
+            scene.nodes.len(), primitive_count, scene.materials.len(), self.draws.len()
+        );
+        self.scene = Some(scene);
+        Ok(())
+    }
+
+    /// Creates a DEVICE_LOCAL buffer holding `data`. With unified memory the buffer is mapped and written
+    /// directly, otherwise the copy from staging memory is recorded into `upload_context`.
+    fn create_buffer_with_data(
+        &self,
+        upload_context : &mut upload::UploadContext,
+        data : &[u8],
+        usage : vk::BufferUsageFlags
+    ) -> Result<(vk::Buffer, Allocation), RendererError> {
+        let device_ref = self.device.as_ref().unwrap();
+        let buffer_size = data.len() as vk::DeviceSize;
+
+        if self.unified_memory {
+            let buffer = match self.create_buffer(
+                buffer_size,
+                usage,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            ) {
+                Ok(buffer) => Some(buffer),
+                // the heaps are unified but no type is host visible for this usage, so stage after all
+                Err(RendererError::NoSuitableMemoryType) => None,
+                Err(error) => return Err(error)
+            };
+
+            if let Some(buffer) = buffer {
+                unsafe{
+                    buffer.1.mapped_ptr().unwrap().copy_from_nonoverlapping(data.as_ptr(), data.len());
+                }
+                return Ok(buffer);
+            }
+        }
+
+        let buffer = self.create_buffer(
+            buffer_size, 
+            vk::BufferUsageFlags::TRANSFER_DST | usage, 
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+        )?;
+
+        let copy = upload_context.stage(self.allocator.as_ref().unwrap(), data).and_then(|(staging_buffer, offset)| {
+            let command_buffer = upload_context.command_buffer()?;
+            let copy_region = [vk::BufferCopy {
+                src_offset : offset,
+                dst_offset : 0,
+                size : buffer_size
+            }];
+            unsafe{
+                device_ref.cmd_copy_buffer(command_buffer, staging_buffer, buffer.0, &copy_region);
+            }
+            Ok(())
+        });
+
+        match copy {
+            Ok(()) => Ok(buffer),
+            Err(error) => {
+                self.destroy_buffer(buffer);
+                Err(error)
+            }
+        }
+    }
+
+    fn create_vertex_buffer(&self, upload_context : &mut upload::UploadContext, vertices : &[Vertex]) -> Result<(vk::Buffer, Allocation), RendererError> {
+        let data = unsafe{
+            std::slice::from_raw_parts(vertices.as_ptr() as *const u8, std::mem::size_of_val(vertices))
         };
+        self.create_buffer_with_data(upload_context, data, vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
 
-        let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo {
-            s_type : vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            stage : vk::ShaderStageFlags::FRAGMENT,
-            module : frag_shader_module,
-            p_name : name.as_ptr() as *const i8,
-            flags : PipelineShaderStageCreateFlags::empty(),
-            p_specialization_info : std::ptr::null()
+    fn create_index_buffer(&self, upload_context : &mut upload::UploadContext, indices : &[u32], index_type : vk::IndexType) -> Result<(vk::Buffer, Allocation), RendererError> {
+        let data : Vec<u8> = match index_type {
+            vk::IndexType::UINT16 => indices.iter().flat_map(|&index| (index as u16).to_ne_bytes()).collect(),
+            _ => indices.iter().flat_map(|&index| index.to_ne_bytes()).collect()
         };
+        self.create_buffer_with_data(upload_context, &data, vk::BufferUsageFlags::INDEX_BUFFER)
+    }
 
-        let shader_stages = [vert_shader_stage_info, frag_shader_stage_info];
+    /// Uploads the vertices and indices of every scene primitive, using 16 bit indices whenever they fit.
+    fn create_primitive_buffers(&mut self) -> Result<(), RendererError> {
+        self.with_upload_context(|app, upload_context| {
+            let scene = app.scene.as_ref().unwrap();
+
+            for primitive in scene.meshes.iter().flat_map(|mesh| mesh.primitives.iter()) {
+                // 0xFFFF is the primitive restart value of 16 bit indices on many drivers, so it is never used
+                let index_type = match primitive.vertices.len() >= u16::MAX as usize {
+                    true => vk::IndexType::UINT32,
+                    false => vk::IndexType::UINT16
+                };
+
+                let vertex_buffer = app.create_vertex_buffer(upload_context, &primitive.vertices)?;
+                let index_buffer = match app.create_index_buffer(upload_context, &primitive.indices, index_type) {
+                    Ok(index_buffer) => index_buffer,
+                    Err(error) => {
+                        app.destroy_buffer(vertex_buffer);
+                        return Err(error);
+                    }
+                };
+
+                // pushed right away so Drop can release it even if a later primitive fails
+                app.primitives.push(GpuPrimitive {
+                    vertex_buffer : vertex_buffer.0,
+                    vertex_buffer_allocation : vertex_buffer.1,
+                    index_buffer : index_buffer.0,
+                    index_buffer_allocation : index_buffer.1,
+                    index_count : primitive.indices.len() as u32,
+                    index_type : index_type,
+                    material : primitive.material
+                });
+            }
+            Ok(())
+        })
+    }
 
-        let binding_description = Vertex::get_binding_destcription();
-        let attribute_description = Vertex::get_attribute_descripyions();
+    /// Creates one uniform buffer per frame in flight.
+    fn create_uniform_buffers(&mut self) -> Result<(), RendererError> {
+        let buffer_size = std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize;
 
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
-            s_type : vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            vertex_binding_description_count : 1,
-            p_vertex_binding_descriptions : &binding_description as *const VertexInputBindingDescription,
-            vertex_attribute_description_count : attribute_description.as_ref().len() as u32,
-            p_vertex_attribute_descriptions : &attribute_description as *const vk::VertexInputAttributeDescription,
-            flags : vk::PipelineVertexInputStateCreateFlags::empty()
-        };
+        for _ in 0..self.options.max_frames_in_flight {
+            let buffer = self.create_buffer(
+                buffer_size, 
+                vk::BufferUsageFlags::UNIFORM_BUFFER, 
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            )?;
+            self.uniform_buffers.push(buffer.0);
+            self.uniform_buffers_allocations.push(buffer.1);
+        }
+        Ok(())
+    }
+
+    fn create_descriptor_pool(&mut self) -> Result<(), RendererError> {
+        // one set per frame in flight and material
+        let set_count = (self.options.max_frames_in_flight * self.material_textures.len()) as u32;
+
+        let pool_sizes : Vec<vk::DescriptorPoolSize> = self.pipeline_interface.as_ref().unwrap()
+            .descriptor_bindings.iter()
+            .map(|binding| vk::DescriptorPoolSize {
+                ty : binding.descriptor_type,
+                descriptor_count : binding.count * set_count
+            })
+            .collect();
 
-        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo{
-            s_type : vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type : vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
             p_next : std::ptr::null(),
-            topology : vk::PrimitiveTopology::TRIANGLE_LIST,
-            primitive_restart_enable : 0,
-            flags : vk::PipelineInputAssemblyStateCreateFlags::empty()
+            pool_size_count : pool_sizes.len() as u32,
+            p_pool_sizes : pool_sizes.as_ptr(),
+            max_sets : set_count,
+            flags : vk::DescriptorPoolCreateFlags::empty()
         };
 
-        let viewport = vk::Viewport{
-            x : 0.0,
-            y : 0.0,
-            width : self.swap_chain_extent.as_ref().unwrap().width as f32,
-            height : self.swap_chain_extent.as_ref().unwrap().height as f32,
-            min_depth : 0.0,
-            max_depth : 1.0
-        };
+        self.descriptor_pool = Some(unsafe {
+            self.device.as_ref().unwrap()
+            .create_descriptor_pool(&pool_info, None)
+            .context("failed to create descriptor pool!")?
+        });
+        Ok(())
+    }
 
-        let scissor = vk::Rect2D {
-            offset : vk::Offset2D { x: 0, y: 0 },
-            extent : *self.swap_chain_extent.as_ref().unwrap()
-        };
+    /// Allocates the set for frame in flight `f` and material `m` at `f * material_count + m`.
+    fn create_descriptor_sets(&mut self) -> Result<(), RendererError> {
+        let material_count = self.material_textures.len();
+        let set_count = self.options.max_frames_in_flight * material_count;
+        let layouts = vec![*self.descriptor_set_layout.as_ref().unwrap(); set_count];
 
-        let viewport_state = vk::PipelineViewportStateCreateInfo {
-            s_type : vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type : vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
             p_next : std::ptr::null(),
-            viewport_count : 1,
-            p_viewports : &viewport as *const vk::Viewport,
-            scissor_count : 1,
-            p_scissors : &scissor as *const vk::Rect2D,
-            flags : vk::PipelineViewportStateCreateFlags::empty()
+            descriptor_pool : *self.descriptor_pool.as_ref().unwrap(),
+            descriptor_set_count : set_count as u32,
+            p_set_layouts : layouts.as_ptr()
         };
 
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
-            s_type : vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            depth_clamp_enable : 0,
-            rasterizer_discard_enable : 0,
-            polygon_mode : vk::PolygonMode::FILL,
-            line_width : 1.0,
-            cull_mode : vk::CullModeFlags::BACK,
-            front_face : vk::FrontFace::CLOCKWISE,
-            depth_bias_enable : 0,
-            depth_bias_constant_factor : 0.0,
-            depth_bias_clamp : 0.0,
-            depth_bias_slope_factor : 0.0,
-            flags : vk::PipelineRasterizationStateCreateFlags::empty()
-        };
-
-        let multisampling = vk::PipelineMultisampleStateCreateInfo {
-            s_type : vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            sample_shading_enable : 0,
-            rasterization_samples : vk::SampleCountFlags::TYPE_1,
-            min_sample_shading : 1.0,
-            p_sample_mask : std::ptr::null(),
-            alpha_to_coverage_enable : 0,
-            alpha_to_one_enable : 0,
-            flags : vk::PipelineMultisampleStateCreateFlags::empty()
-        };
-
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
-            color_write_mask : vk::ColorComponentFlags::all(),
-            blend_enable : 0,
-            src_color_blend_factor : vk::BlendFactor::ONE,
-            dst_color_blend_factor : vk::BlendFactor::ZERO,
-            color_blend_op : vk::BlendOp::ADD,
-            src_alpha_blend_factor : vk::BlendFactor::ONE,
-            dst_alpha_blend_factor : vk::BlendFactor::ZERO,
-            alpha_blend_op : vk::BlendOp::ADD
-        };
-
-        let blend_constants = [0.0, 0.0, 0.0, 0.0];
-
-        let color_blending = vk::PipelineColorBlendStateCreateInfo {
-            s_type : vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            logic_op_enable : 0,
-            logic_op : vk::LogicOp::COPY,
-            attachment_count : 1,
-            p_attachments : &color_blend_attachment as *const PipelineColorBlendAttachmentState,
-            blend_constants : blend_constants,
-            flags : vk::PipelineColorBlendStateCreateFlags::empty()
+        let device_ref = self.device.as_ref().unwrap();
+
+        self.descriptor_sets = unsafe {
+            device_ref.allocate_descriptor_sets(&alloc_info)
+            .context("failed to allocate descriptor sets!")?
         };
 
-        let dynamic_states = [
-            vk::DynamicState::VIEWPORT,
-            vk::DynamicState::LINE_WIDTH
+        for (idx, descriptor_set) in self.descriptor_sets.iter().enumerate() {
+            let buffer_info = vk::DescriptorBufferInfo {
+                buffer : self.uniform_buffers[idx / material_count],
+                offset : 0,
+                range : std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize
+            };
+
+            // sampled images ignore the sampler and samplers ignore the view and layout, so one image info
+            // serves every texture binding layout in SCENE_DESCRIPTOR_BINDINGS
+            let image_info = vk::DescriptorImageInfo {
+                sampler : *self.texture_sampler.as_ref().unwrap(),
+                image_view : self.textures[self.material_textures[idx % material_count]].view,
+                image_layout : vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            };
+
+            let descriptor_write : Vec<vk::WriteDescriptorSet> = self.pipeline_interface.as_ref().unwrap()
+                .descriptor_bindings.iter()
+                .map(|binding| {
+                    let is_buffer = binding.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER;
+                    vk::WriteDescriptorSet {
+                        s_type : vk::StructureType::WRITE_DESCRIPTOR_SET,
+                        p_next : std::ptr::null(),
+                        dst_set : *descriptor_set,
+                        dst_binding : binding.binding,
+                        dst_array_element : 0,
+                        descriptor_type : binding.descriptor_type,
+                        descriptor_count : 1,
+                        p_buffer_info : match is_buffer {
+                            true => &buffer_info as *const vk::DescriptorBufferInfo,
+                            false => std::ptr::null()
+                        },
+                        p_image_info : match is_buffer {
+                            true => std::ptr::null(),
+                            false => &image_info as *const vk::DescriptorImageInfo
+                        },
+                        p_texel_buffer_view : std::ptr::null()
+                    }
+                })
+                .collect();
+
+            unsafe {
+                device_ref.update_descriptor_sets(&descriptor_write, &[]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Derives the descriptor set layout from the scene shaders. The layout outlives shader reloads, which
+    /// therefore have to keep the descriptor interface unchanged.
+    fn create_descriptor_set_layout(&mut self) -> Result<(), RendererError> {
+        let (_, _, interface) = self.load_scene_shaders()?;
+
+        let device_ref = self.device.as_ref().unwrap();
+        let mut set_layouts = interface.create_set_layouts(device_ref)?;
+        if set_layouts.len() != 1 {
+            for set_layout in set_layouts {
+                unsafe{ device_ref.destroy_descriptor_set_layout(set_layout, None); }
+            }
+            return Err(RendererError::ShaderInterface(format!(
+                "the shaders use {} descriptor sets, but the renderer binds exactly one", interface.set_count()
+            )));
+        }
+
+        self.descriptor_set_layout = set_layouts.pop();
+        self.pipeline_interface = Some(interface);
+        Ok(())
+    }
+
+    /// Loads and reflects the scene shaders. Their descriptor bindings have to be exactly the ones
+    /// `create_descriptor_sets` writes.
+    fn load_scene_shaders(&self) -> Result<(Vec<u32>, Vec<u32>, reflection::PipelineInterface), RendererError> {
+        let vert_shader_path = std::path::Path::new(VERT_SHADER_PATH);
+        let frag_shader_path = std::path::Path::new(FRAG_SHADER_PATH);
+        let vert_shader_code = load_shader(vert_shader_path, vk::ShaderStageFlags::VERTEX)?;
+        let frag_shader_code = load_shader(frag_shader_path, vk::ShaderStageFlags::FRAGMENT)?;
+
+        let reflect = |path : &std::path::Path, code : &[u32], stage : vk::ShaderStageFlags| {
+            reflection::ShaderReflection::reflect(code)
+                .and_then(|reflection| match reflection.stage == stage {
+                    true => Ok(reflection),
+                    // precompiled SPIR-V carries its own stage
+                    false => Err(format!("expected a {:?} shader, found a {:?} shader", stage, reflection.stage))
+                })
+                .map_err(|message| RendererError::ShaderInterface(format!("{}: {}", path.display(), message)))
+        };
+        let stages = [
+            reflect(vert_shader_path, &vert_shader_code, vk::ShaderStageFlags::VERTEX)?,
+            reflect(frag_shader_path, &frag_shader_code, vk::ShaderStageFlags::FRAGMENT)?
         ];
+        let interface = reflection::PipelineInterface::merge(&stages).map_err(RendererError::ShaderInterface)?;
 
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
-            s_type : vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            dynamic_state_count : 2,
-            p_dynamic_states : dynamic_states.as_ptr(),
-            flags : vk::PipelineDynamicStateCreateFlags::empty()
+        let push_constants_size = std::mem::size_of::<DrawPushConstants>() as u32;
+        if interface.push_constant_ranges.is_empty() {
+            return Err(RendererError::ShaderInterface("the shaders declare no push constant block".to_owned()));
+        }
+        if let Some(range) = interface.push_constant_ranges.iter().find(|range| range.size != push_constants_size) {
+            return Err(RendererError::ShaderInterface(format!(
+                "the {:?} push constant block is {} bytes, but DrawPushConstants is {}", range.stage_flags, range.size, push_constants_size
+            )));
+        }
+
+        let declared : Vec<(u32, u32, vk::DescriptorType, u32)> = interface.descriptor_bindings.iter()
+            .map(|binding| (binding.set, binding.binding, binding.descriptor_type, binding.count))
+            .collect();
+        let provided = SCENE_DESCRIPTOR_BINDINGS.iter().any(|bindings| {
+            let expected : Vec<(u32, u32, vk::DescriptorType, u32)> = bindings.iter()
+                .map(|&(binding, descriptor_type)| (0, binding, descriptor_type, 1))
+                .collect();
+            declared == expected
+        });
+        if !provided {
+            return Err(RendererError::ShaderInterface(format!(
+                "the shaders declare {:?} as (set, binding, type, count), but the renderer provides set 0 with either {:?} or {:?}",
+                declared, SCENE_DESCRIPTOR_BINDINGS[0], SCENE_DESCRIPTOR_BINDINGS[1]
+            )));
+        }
+
+        Ok((vert_shader_code, frag_shader_code, interface))
+    }
+
+    fn create_graphics_pipeline(&mut self) -> Result<(), RendererError> {
+        if self.pipeline_layout.is_none() {
+            self.create_pipeline_layout()?;
+        }
+        self.pipelines = Some(self.build_scene_pipelines()?);
+        Ok(())
+    }
+
+    fn create_pipeline_layout(&mut self) -> Result<(), RendererError> {
+        self.pipeline_layout = Some(self.pipeline_interface.as_ref().unwrap().create_pipeline_layout(
+            self.device.as_ref().unwrap(),
+            &[*self.descriptor_set_layout.as_ref().unwrap()]
+        )?);
+        Ok(())
+    }
+
+    /// Builds the opaque, transparent and wireframe pipelines from the shaders currently on disk. Nothing is
+    /// left behind on failure, so the caller can keep using its previous pipelines.
+    fn build_scene_pipelines(&self) -> Result<ScenePipelines, RendererError> {
+        let device_ref = self.device.as_ref().unwrap();
+
+        let (vert_shader_code, frag_shader_code, interface) = self.load_scene_shaders()?;
+        if !interface.layout_matches(self.pipeline_interface.as_ref().unwrap()) {
+            return Err(RendererError::ShaderInterface(
+                "descriptor bindings or push constants changed, which needs a restart".to_owned()
+            ));
+        }
+
+        // attributes the shader ignores are left out, missing or mistyped ones are an error
+        let vertex_attributes = interface.vertex_attributes("Vertex", &Vertex::get_attribute_descripyions())
+            .map_err(RendererError::ShaderInterface)?;
+
+        let vert_shader_module = self.create_shader_module(&vert_shader_code)?;
+        let frag_shader_module = match self.create_shader_module(&frag_shader_code) {
+            Ok(frag_shader_module) => frag_shader_module,
+            Err(error) => {
+                unsafe{ device_ref.destroy_shader_module(vert_shader_module, None); }
+                return Err(error);
+            }
         };
 
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
-            s_type : vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
-            p_next : std::ptr::null(),
-            set_layout_count : 1,
-            p_set_layouts : self.descriptor_set_layout.as_ref().unwrap() as *const vk::DescriptorSetLayout,
-            push_constant_range_count : 0,
-            p_push_constant_ranges : std::ptr::null(),
-            flags : vk::PipelineLayoutCreateFlags::empty()
+        let pipeline_cache = self.pipeline_cache.as_ref().unwrap().handle();
+        let opaque = pipeline::GraphicsPipelineBuilder::new()
+            .shader(vk::ShaderStageFlags::VERTEX, vert_shader_module)
+            .shader(vk::ShaderStageFlags::FRAGMENT, frag_shader_module)
+            .vertex_input(&[Vertex::get_binding_destcription()], &vertex_attributes)
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST, false)
+            .layout(*self.pipeline_layout.as_ref().unwrap())
+            .render_pass(*self.render_pass.as_ref().unwrap(), 0);
+
+        // blended surfaces are sorted after the opaque ones and must not hide what lies behind them
+        let transparent = opaque.clone()
+            .depth(true, false, vk::CompareOp::LESS)
+            .blend_attachments(&[pipeline::alpha_blend_attachment()]);
+
+        let instance_ref = self.instance.as_ref().unwrap();
+        let supported_features = unsafe{ instance_ref.get_physical_device_features(*self.physical_device.as_ref().unwrap()) };
+        let wireframe = match supported_features.fill_mode_non_solid == vk::TRUE {
+            true => Some(opaque.clone()
+                .polygon_mode(vk::PolygonMode::LINE)
+                .line_width(1.0)
+                .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)),
+            false => None
         };
 
-        self.pipeline_layout = Some(unsafe{
-            self.device.as_ref().unwrap()
-            .create_pipeline_layout(&pipeline_layout_info, None)
-            .expect("failed to create pipeline layout")
-        });
+        let mut built : Vec<vk::Pipeline> = Vec::new();
+        let result = [Some(&opaque), Some(&transparent), wireframe.as_ref()].iter()
+            .flatten()
+            .try_for_each(|builder| {
+                built.push(builder.build(device_ref, pipeline_cache)?);
+                Ok(())
+            });
 
-        let pipeline_info = [vk::GraphicsPipelineCreateInfo {
-            s_type : vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
-            p_next : std::ptr::null(),
-            stage_count : 2,
-            p_stages : &shader_stages as *const PipelineShaderStageCreateInfo,
-            p_vertex_input_state : &vertex_input_info as *const PipelineVertexInputStateCreateInfo,
-            p_input_assembly_state : &input_assembly as *const PipelineInputAssemblyStateCreateInfo,
-            p_viewport_state : &viewport_state as *const PipelineViewportStateCreateInfo,
-            p_rasterization_state : &rasterizer as *const PipelineRasterizationStateCreateInfo,
-            p_multisample_state : &multisampling as *const PipelineMultisampleStateCreateInfo,
-            p_depth_stencil_state : std::ptr::null(),
-            p_color_blend_state : &color_blending as *const PipelineColorBlendStateCreateInfo,
-            p_dynamic_state : std::ptr::null(),
-            layout : *self.pipeline_layout.as_ref().unwrap(),
-            render_pass : *self.render_pass.as_ref().unwrap(),
-            subpass : 0,
-            base_pipeline_handle : vk::Pipeline::null(),
-            base_pipeline_index : -1,
-            flags : vk::PipelineCreateFlags::empty(),
-            p_tessellation_state : std::ptr::null()
-        }];
+        // the modules are only needed while the pipelines are being created
+        unsafe{
+            device_ref.destroy_shader_module(vert_shader_module, None);
+            device_ref.destroy_shader_module(frag_shader_module, None);
+        }
 
-        self.graphics_pipeline = Some(unsafe {
-            self.device.as_ref().unwrap()
-            .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_info, None)
-            .expect("failed to create graphics pipeline!")[0]
+        match result {
+            Ok(()) => Ok(ScenePipelines {
+                opaque : built[0],
+                transparent : built[1],
+                wireframe : built.get(2).copied()
+            }),
+            Err(error) => {
+                for pipeline in built {
+                    unsafe{ device_ref.destroy_pipeline(pipeline, None); }
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Rebuilds the scene pipelines when one of their shaders is among `changed`. A shader that fails to load
+    /// or compile is reported and the previous pipelines stay in use.
+    fn reload_shaders(&mut self, changed : &[std::path::PathBuf]) -> Result<(), RendererError> {
+        let affected = changed.iter().any(|path| {
+            path == std::path::Path::new(VERT_SHADER_PATH) || path == std::path::Path::new(FRAG_SHADER_PATH)
         });
+        if !affected {
+            return Ok(());
+        }
+
+        // pipelines still referenced by frames in flight must not be destroyed
+        unsafe{
+            self.device.as_ref().unwrap()
+            .device_wait_idle()
+            .context("failed to wait for device idle")?;
+        }
+
+        let pipelines = match self.build_scene_pipelines() {
+            Ok(pipelines) => pipelines,
+            Err(error) => {
+                log::error!("shader reload failed, keeping the previous pipelines: {}", error);
+                return Ok(());
+            }
+        };
+        if let Some(old_pipelines) = self.pipelines.replace(pipelines) {
+            unsafe{ old_pipelines.destroy(self.device.as_ref().unwrap()); }
+        }
+        log::info!("reloaded shaders");
+
+        // prerecorded command buffers still bind the destroyed pipelines
+        self.rerecord_command_buffers()
     }
 
-    fn create_framebuffers(&mut self){
+    /// Records the prerecorded command buffers again after a pipeline they bind changed. Does nothing when
+    /// command buffers are recorded every frame. The device must be idle.
+    fn rerecord_command_buffers(&mut self) -> Result<(), RendererError> {
+        if let Some(command_buffers) = self.command_buffers.take() {
+            unsafe{
+                self.device.as_ref().unwrap()
+                .free_command_buffers(*self.command_pool.as_ref().unwrap(), &command_buffers);
+            }
+            self.create_command_buffers()?;
+        }
+        Ok(())
+    }
+
+    fn create_framebuffers(&mut self) -> Result<(), RendererError> {
         self.swap_chain_frame_buffers
         .resize(
             self.swap_chain_image_views.len(), 
@@ -1067,12 +2607,14 @@ impl HelloTriangleApplication {
         );
 
         for (idx, image_view) in self.swap_chain_image_views.iter().enumerate(){
+            let attachments = [*image_view, *self.depth_image_view.as_ref().unwrap()];
+
             let framebuffer_info = vk::FramebufferCreateInfo{
                 s_type : vk::StructureType::FRAMEBUFFER_CREATE_INFO,
                 p_next : std::ptr::null(),
                 render_pass : *self.render_pass.as_ref().unwrap(),
-                attachment_count : 1,
-                p_attachments : image_view as *const vk::ImageView,
+                attachment_count : attachments.len() as u32,
+                p_attachments : attachments.as_ptr(),
                 width : self.swap_chain_extent.as_ref().unwrap().width,
                 height : self.swap_chain_extent.as_ref().unwrap().height,
                 layers : 1,
@@ -1082,12 +2624,13 @@ impl HelloTriangleApplication {
             self.swap_chain_frame_buffers[idx] = unsafe {
                 self.device.as_ref().unwrap()
                 .create_framebuffer(&framebuffer_info, None)
-                .expect("failed to create framebuffer!")
+                .context("failed to create framebuffer!")?
             };
         }
+        Ok(())
     }
 
-    fn create_command_pool(&mut self){
+    fn create_command_pool(&mut self) -> Result<(), RendererError> {
         let queue_family_indices = self.find_queue_families(self.physical_device.as_ref().unwrap());
 
         let pool_info = vk::CommandPoolCreateInfo {
@@ -1100,80 +2643,241 @@ impl HelloTriangleApplication {
         self.command_pool = Some(unsafe {
             self.device.as_ref().unwrap()
             .create_command_pool(&pool_info, None)
-            .expect("failed to create command pool!")
+            .context("failed to create command pool!")?
         });
+        Ok(())
     }
 
-    fn create_command_buffers(&mut self){
+    fn create_upload_context(&mut self) -> Result<(), RendererError> {
+        let queue_family_indices = self.find_queue_families(self.physical_device.as_ref().unwrap());
+        let memory_properties = unsafe{
+            self.instance.as_ref().unwrap()
+            .get_physical_device_memory_properties(*self.physical_device.as_ref().unwrap())
+        };
+
+        self.unified_memory = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].iter()
+            .all(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL));
+        log::info!("memory heaps are {}", match self.unified_memory { true => "unified", false => "split" });
+
+        self.upload_context = Some(upload::UploadContext::new(
+            self.device.as_ref().unwrap(),
+            *self.graphics_queue.as_ref().unwrap(),
+            queue_family_indices.0.unwrap() as u32
+        )?);
+        Ok(())
+    }
+
+    /// Runs `f` with the upload context moved out of `self`, so that `f` can still borrow the renderer.
+    fn with_upload_context<T>(
+        &mut self,
+        f : impl FnOnce(&mut Self, &mut upload::UploadContext) -> Result<T, RendererError>
+    ) -> Result<T, RendererError> {
+        let mut upload_context = self.upload_context.take().unwrap();
+        let result = f(self, &mut upload_context);
+        self.upload_context = Some(upload_context);
+        result
+    }
+
+    /// Records one command buffer per frame in flight and swap chain image, at
+    /// `frame * image_count + image`, since each frame in flight binds its own descriptor sets.
+    fn create_command_buffers(&mut self) -> Result<(), RendererError> {
+        let image_count = self.swap_chain_frame_buffers.len();
         let alloc_info = vk::CommandBufferAllocateInfo {
             s_type : vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
             p_next : std::ptr::null(),
             command_pool : *self.command_pool.as_ref().unwrap(),
             level : vk::CommandBufferLevel::PRIMARY,
-            command_buffer_count : self.swap_chain_frame_buffers.len() as u32,
+            command_buffer_count : (self.options.max_frames_in_flight * image_count) as u32,
         };
         
         self.command_buffers = Some(unsafe {
             self.device.as_ref().unwrap()
             .allocate_command_buffers(&alloc_info)
-            .expect("failed to allocate command buffers!")
+            .context("failed to allocate command buffers!")?
         });
 
+        for (idx, command_buffer) in self.command_buffers.as_ref().unwrap().iter().enumerate() {
+            self.record_command_buffer(*command_buffer, CommandBufferUsageFlags::empty(), idx / image_count, idx % image_count)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a resettable pool with a single command buffer for every frame in flight.
+    fn create_frame_command_pools(&mut self) -> Result<(), RendererError> {
+        let queue_family_indices = self.find_queue_families(self.physical_device.as_ref().unwrap());
         let device_ref = self.device.as_ref().unwrap();
 
-        for (idx, command_buffer) in self.command_buffers.as_ref().unwrap().iter().enumerate() {
-            let begin_info = vk::CommandBufferBeginInfo {
-                s_type : vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
-                p_next : std::ptr::null(),
-                flags : CommandBufferUsageFlags::empty(),
-                p_inheritance_info : std::ptr::null()
+        let pool_info = vk::CommandPoolCreateInfo {
+            s_type : vk::StructureType::COMMAND_POOL_CREATE_INFO,
+            p_next : std::ptr::null(),
+            queue_family_index : queue_family_indices.0.unwrap() as u32,
+            flags : vk::CommandPoolCreateFlags::TRANSIENT
+        };
+
+        for _ in 0..self.options.max_frames_in_flight {
+            let command_pool = unsafe {
+                device_ref.create_command_pool(&pool_info, None)
+                .context("failed to create command pool!")?
             };
+            self.frame_command_pools.push(command_pool);
 
-            unsafe{
-                device_ref.begin_command_buffer(*command_buffer, &begin_info)
-                .expect("failed to begin recording command buffer");
-            }
+            let alloc_info = vk::CommandBufferAllocateInfo {
+                s_type : vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+                p_next : std::ptr::null(),
+                command_pool : command_pool,
+                level : vk::CommandBufferLevel::PRIMARY,
+                command_buffer_count : 1,
+            };
 
-            let render_area = vk::Rect2D {
-                offset : vk::Offset2D { x: 0, y: 0 },
-                extent : *self.swap_chain_extent.as_ref().unwrap()
+            let command_buffers = unsafe {
+                device_ref.allocate_command_buffers(&alloc_info)
+                .context("failed to allocate command buffers!")?
             };
+            self.frame_command_buffers.push(command_buffers[0]);
+        }
+        Ok(())
+    }
+
+    /// Returns the command buffer that draws `image_index` in the current frame. Unless the buffers are
+    /// pre-recorded, the frame's pool is reset and the buffer recorded from the current draw list, so the
+    /// frame's fence must have been waited on.
+    fn frame_command_buffer(&self, image_index : u32) -> Result<vk::CommandBuffer, RendererError> {
+        if self.options.prerecord_commands {
+            let command_buffer_index = self.current_frame * self.swap_chain_frame_buffers.len() + image_index as usize;
+            return Ok(self.command_buffers.as_ref().unwrap()[command_buffer_index]);
+        }
 
-            let clear_color = vk::ClearValue {
+        let command_buffer = self.frame_command_buffers[self.current_frame];
+        unsafe{
+            self.device.as_ref().unwrap()
+            .reset_command_pool(self.frame_command_pools[self.current_frame], vk::CommandPoolResetFlags::empty())
+            .context("failed to reset command pool")?;
+        }
+        self.record_command_buffer(command_buffer, CommandBufferUsageFlags::ONE_TIME_SUBMIT, self.current_frame, image_index as usize)?;
+        Ok(command_buffer)
+    }
+
+    /// Records the render pass drawing every scene draw into framebuffer `image`, using the descriptor sets
+    /// and uniform buffer of frame in flight `frame`.
+    fn record_command_buffer(
+        &self,
+        command_buffer : vk::CommandBuffer,
+        flags : CommandBufferUsageFlags,
+        frame : usize,
+        image : usize
+    ) -> Result<(), RendererError> {
+        let device_ref = self.device.as_ref().unwrap();
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type : vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            p_next : std::ptr::null(),
+            flags : flags,
+            p_inheritance_info : std::ptr::null()
+        };
+
+        unsafe{
+            device_ref.begin_command_buffer(command_buffer, &begin_info)
+            .context("failed to begin recording command buffer")?;
+        }
+
+        let render_area = vk::Rect2D {
+            offset : vk::Offset2D { x: 0, y: 0 },
+            extent : *self.swap_chain_extent.as_ref().unwrap()
+        };
+
+        let clear_values = [
+            vk::ClearValue {
                 color : ClearColorValue{ float32: [0.0f32, 0.0f32, 0.0f32, 1.0f32] },
+            },
+            vk::ClearValue {
+                depth_stencil : vk::ClearDepthStencilValue { depth : 1.0, stencil : 0 },
+            }
+        ];
+        
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type : vk::StructureType::RENDER_PASS_BEGIN_INFO,
+            p_next : std::ptr::null(),
+            render_pass : *self.render_pass.as_ref().unwrap(),
+            framebuffer : self.swap_chain_frame_buffers[image],
+            render_area : render_area,
+            clear_value_count : clear_values.len() as u32,
+            p_clear_values : clear_values.as_ptr()
+        };
+
+        unsafe{
+            device_ref.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+
+            let viewport = vk::Viewport {
+                x : 0.0,
+                y : 0.0,
+                width : render_area.extent.width as f32,
+                height : render_area.extent.height as f32,
+                min_depth : 0.0,
+                max_depth : 1.0
             };
-            
-            let render_pass_info = vk::RenderPassBeginInfo {
-                s_type : vk::StructureType::RENDER_PASS_BEGIN_INFO,
-                p_next : std::ptr::null(),
-                render_pass : *self.render_pass.as_ref().unwrap(),
-                framebuffer : self.swap_chain_frame_buffers[idx],
-                render_area : render_area,
-                clear_value_count : 1,
-                p_clear_values : &clear_color as *const vk::ClearValue
+            device_ref.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device_ref.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+            let pipelines = self.pipelines.as_ref().unwrap();
+            let opaque_pipeline = match self.wireframe {
+                true => pipelines.wireframe.unwrap_or(pipelines.opaque),
+                false => pipelines.opaque
             };
 
-            unsafe{
-                device_ref.cmd_begin_render_pass(*command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
-                device_ref.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, *self.graphics_pipeline.as_ref().unwrap());
+            // opaque draws first, so that blended ones composite over the finished depth buffer
+            let ordered_draws = self.draws.iter().filter(|draw| !draw.transparent)
+                .chain(self.draws.iter().filter(|draw| draw.transparent));
+
+            let world_transforms = self.scene.as_ref().unwrap().world_transforms();
+            let pipeline_layout = *self.pipeline_layout.as_ref().unwrap();
+            let push_constant_stages = self.pipeline_interface.as_ref().unwrap().push_constant_stages();
+
+            let material_count = self.material_textures.len();
+            let mut bound_pipeline = vk::Pipeline::null();
+            for draw in ordered_draws {
+                let pipeline = match draw.transparent {
+                    true => pipelines.transparent,
+                    false => opaque_pipeline
+                };
+                if pipeline != bound_pipeline {
+                    device_ref.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    bound_pipeline = pipeline;
+                }
+
+                let primitive = &self.primitives[draw.primitive];
 
-                let vertex_buffers = [*self.vertex_buffer.as_ref().unwrap()];
+                let vertex_buffers = [primitive.vertex_buffer];
                 let offsets = [0];
-                device_ref.cmd_bind_vertex_buffers(*command_buffer, 0, &vertex_buffers, &offsets);
+                device_ref.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
+
+                device_ref.cmd_bind_index_buffer(command_buffer, primitive.index_buffer, 0, primitive.index_type);
+
+                let set_idx = frame * material_count + primitive.material;
+                device_ref.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &self.descriptor_sets[set_idx..set_idx + 1],
+                    &[]
+                );
 
-                device_ref.cmd_bind_index_buffer(*command_buffer, *self.index_buffer.as_ref().unwrap(), 0, vk::IndexType::UINT16);
+                let push_constants = DrawPushConstants { model : world_transforms[draw.node] };
+                pipeline::push_constants(device_ref, command_buffer, pipeline_layout, push_constant_stages, &push_constants);
 
-                device_ref.cmd_draw_indexed(*command_buffer, self.indices.len() as u32, 1, 0, 0, 0);
-                device_ref.cmd_end_render_pass(*command_buffer);
-                device_ref.end_command_buffer(*command_buffer).expect("failed to record command buffer");
+                device_ref.cmd_draw_indexed(command_buffer, primitive.index_count, 1, 0, 0, 0);
             }
+            device_ref.cmd_end_render_pass(command_buffer);
+            device_ref.end_command_buffer(command_buffer).context("failed to record command buffer")?;
         }
+        Ok(())
     }
 
-    fn create_sync_objects(&mut self){
-        self.image_available_semaphores.resize(2, vk::Semaphore::null());
-        self.render_finished_semaphores.resize(2, vk::Semaphore::null());
-        self.in_flight_fences.resize(2, vk::Fence::null());
+    fn create_sync_objects(&mut self) -> Result<(), RendererError> {
+        let max_frames_in_flight = self.options.max_frames_in_flight;
+        self.image_available_semaphores.resize(max_frames_in_flight, vk::Semaphore::null());
+        self.render_finished_semaphores.resize(max_frames_in_flight, vk::Semaphore::null());
+        self.in_flight_fences.resize(max_frames_in_flight, vk::Fence::null());
         self.images_in_flight.resize(self.swap_chain_images.as_ref().unwrap().len(), vk::Fence::null());
 
         let semaphore_info = vk::SemaphoreCreateInfo {
@@ -1190,44 +2894,48 @@ impl HelloTriangleApplication {
 
         let device_ref = self.device.as_ref().unwrap();
 
-        for idx in 0..2{
+        for idx in 0..max_frames_in_flight {
             self.image_available_semaphores[idx] = unsafe {
                 device_ref.create_semaphore(&semaphore_info, None)
-                .expect("failed to create synchronization objects for a frame!")
+                .context("failed to create synchronization objects for a frame!")?
             };
 
             self.render_finished_semaphores[idx] = unsafe {
                 device_ref.create_semaphore(&semaphore_info, None)
-                .expect("failed to create synchronization objects for a frame!")
+                .context("failed to create synchronization objects for a frame!")?
             };
 
             self.in_flight_fences[idx] = unsafe {
                 device_ref.create_fence(&fence_info, None)
-                .expect("failed to create synchronization objects for a frame!")
+                .context("failed to create synchronization objects for a frame!")?
             }
         }
+        Ok(())
     }
 
-    fn update_uniform_buffer(&mut self, current_image : u32) {
-        if let None = self.start_time {
-            self.start_time = Some(std::time::SystemTime::now());
-        }
+    /// Writes the camera into the uniform buffer owned by `current_frame`.
+    fn update_uniform_buffer(&mut self, current_frame : usize) -> Result<(), RendererError> {
+        let extent = self.swap_chain_extent.as_ref().unwrap();
+        let ubo = UniformBufferObject {
+            view : self.camera.view(),
+            proj : self.camera.projection(extent.width as f32 / extent.height as f32)
+        };
 
-        let current_time = std::time::SystemTime::now();
-        let time = 
-            current_time.duration_since(*self.start_time.as_ref().unwrap())
-            .unwrap().as_secs_f32();
+        // uniform buffers live in host visible memory, which the allocator keeps mapped
+        let data = self.uniform_buffers_allocations[current_frame].mapped_ptr().unwrap();
+        unsafe{
+            (data as *mut UniformBufferObject).copy_from_nonoverlapping(&ubo, 1);
+        }
+        Ok(())
     }
 
-    fn draw_frame(&mut self){
+    fn draw_frame(&mut self) -> Result<(), RendererError> {
         let fences = [*&self.in_flight_fences[self.current_frame]];
         unsafe{
             self.device.as_ref().unwrap().wait_for_fences(
                 &fences, 
                 true, u64::MAX
-            );
-
-            self.device.as_ref().unwrap().reset_fences(&fences);
+            ).context("fence not work")?;
         }
         let swapchain = ash::extensions::khr::Swapchain::new(
             self.instance.as_ref().unwrap(),
@@ -1241,25 +2949,30 @@ impl HelloTriangleApplication {
                 *&self.image_available_semaphores[self.current_frame], 
                 vk::Fence::null()
             )
-            .expect("failed to load next image")
         };
 
-        let image_index = draw_result.0;
+        // the fence is only reset right before submitting, so returning early here cannot deadlock the next frame
+        let image_index = match draw_result {
+            Ok((image_index, _)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return self.recreate_swap_chain(),
+            Err(result) => return Err(result).context("failed to acquire swap chain image!")
+        };
 
         if self.images_in_flight[image_index as usize] != vk::Fence::null() {
             let image_fences = [*&self.images_in_flight[image_index as usize]];
             unsafe{
                 self.device.as_ref().unwrap()
                 .wait_for_fences(&image_fences, true, u64::MAX)
-                .expect("fence not work");
+                .context("fence not work")?;
             }
         }
 
         self.images_in_flight[image_index as usize] = self.in_flight_fences[self.current_frame];
 
         let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffer = self.frame_command_buffer(image_index)?;
 
-        self.update_uniform_buffer(image_index);
+        self.update_uniform_buffer(self.current_frame)?;
 
         let submit_info = [vk::SubmitInfo {
             s_type : vk::StructureType::SUBMIT_INFO,
@@ -1268,18 +2981,28 @@ impl HelloTriangleApplication {
             p_wait_semaphores : &self.image_available_semaphores[self.current_frame] as *const vk::Semaphore,
             p_wait_dst_stage_mask : wait_stages.as_ptr(),
             command_buffer_count : 1,
-            p_command_buffers : &self.command_buffers.as_ref().unwrap()[image_index as usize] as *const vk::CommandBuffer,
+            p_command_buffers : &command_buffer as *const vk::CommandBuffer,
             signal_semaphore_count : 1,
             p_signal_semaphores : &self.render_finished_semaphores[self.current_frame] as *const vk::Semaphore
         }];
 
         unsafe{
             self.device.as_ref().unwrap()
-            .reset_fences(&fences);
-            
+            .reset_fences(&fences)
+            .context("fence not work")?;
+
             self.device.as_ref().unwrap()
             .queue_submit(*self.graphics_queue.as_ref().unwrap(), &submit_info, self.in_flight_fences[self.current_frame])
-            .expect("failed to submit draw command buffer");
+            .context("failed to submit draw command buffer")?;
+        }
+
+        // the image is read back before it is presented, which hands it over to the presentation engine
+        if let Some(path) = self.pending_screenshot.take() {
+            let image = self.swap_chain_images.as_ref().unwrap()[image_index as usize];
+            // a failed screenshot should not take the whole renderer down with it
+            if let Err(error) = self.save_screenshot(&path, image, vk::ImageLayout::PRESENT_SRC_KHR) {
+                log::error!("{}", error);
+            }
         }
 
         let mut result = vk::Result::NOT_READY;
@@ -1295,87 +3018,457 @@ impl HelloTriangleApplication {
             p_results : &mut result as *mut vk::Result
         };
 
-        unsafe{
+        let present_result = unsafe{
             swapchain
             .queue_present(*self.present_queue.as_ref().unwrap(), &present_info)
-            .expect("failed to present");
+        };
+
+        self.current_frame = (self.current_frame + 1) % self.options.max_frames_in_flight;
+
+        match present_result {
+            Ok(suboptimal) if suboptimal || self.framebuffer_resized => self.recreate_swap_chain(),
+            Ok(_) => Ok(()),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.recreate_swap_chain(),
+            Err(result) => Err(result).context("failed to present swap chain image!")
+        }
+    }
+
+    fn draw_offscreen_frame(&mut self) -> Result<(), RendererError> {
+        let fences = [*&self.in_flight_fences[self.current_frame]];
+        unsafe{
+            self.device.as_ref().unwrap()
+            .wait_for_fences(&fences, true, u64::MAX)
+            .context("fence not work")?;
         }
 
-        self.current_frame = (self.current_frame + 1) % 2;
+        // the offscreen target is a single image, so every frame renders into index 0
+        let image_index : u32 = 0;
 
+        if self.images_in_flight[image_index as usize] != vk::Fence::null() {
+            let image_fences = [*&self.images_in_flight[image_index as usize]];
+            unsafe{
+                self.device.as_ref().unwrap()
+                .wait_for_fences(&image_fences, true, u64::MAX)
+                .context("fence not work")?;
+            }
+        }
+
+        self.images_in_flight[image_index as usize] = self.in_flight_fences[self.current_frame];
+
+        self.update_uniform_buffer(self.current_frame)?;
+        let command_buffer = self.frame_command_buffer(image_index)?;
+
+        let submit_info = [vk::SubmitInfo {
+            s_type : vk::StructureType::SUBMIT_INFO,
+            p_next : std::ptr::null(),
+            wait_semaphore_count : 0,
+            p_wait_semaphores : std::ptr::null(),
+            p_wait_dst_stage_mask : std::ptr::null(),
+            command_buffer_count : 1,
+            p_command_buffers : &command_buffer as *const vk::CommandBuffer,
+            signal_semaphore_count : 0,
+            p_signal_semaphores : std::ptr::null()
+        }];
+
+        unsafe{
+            self.device.as_ref().unwrap()
+            .reset_fences(&fences)
+            .context("fence not work")?;
+
+            self.device.as_ref().unwrap()
+            .queue_submit(*self.graphics_queue.as_ref().unwrap(), &submit_info, self.in_flight_fences[self.current_frame])
+            .context("failed to submit draw command buffer")?;
+        }
+
+        self.current_frame = (self.current_frame + 1) % self.options.max_frames_in_flight;
+        Ok(())
     }
 
-    fn main_loop(&mut self){
+    fn headless_loop(&mut self) -> Result<(), RendererError> {
+        for _ in 0..self.options.frame_count {
+            self.draw_offscreen_frame()?;
+        }
+
+        unsafe{
+            self.device.as_ref().unwrap()
+            .device_wait_idle()
+            .context("failed to wait for device idle")?;
+        }
+
+        match self.options.output.as_ref() {
+            Some(output) => self.save_screenshot(
+                output,
+                self.swap_chain_images.as_ref().unwrap()[0],
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+            ),
+            None => Ok(())
+        }
+    }
+
+    fn toggle_wireframe(&mut self) -> Result<(), RendererError> {
+        if self.pipelines.as_ref().unwrap().wireframe.is_none() {
+            log::warn!("wireframe rendering is not supported by this device");
+            return Ok(());
+        }
+        self.wireframe = !self.wireframe;
+
+        if self.options.prerecord_commands {
+            // prerecorded command buffers may still be executing
+            unsafe{
+                self.device.as_ref().unwrap()
+                .device_wait_idle()
+                .context("failed to wait for device idle")?;
+            }
+            self.rerecord_command_buffers()?;
+        }
+        Ok(())
+    }
+
+    fn main_loop(&mut self) -> Result<(), RendererError> {
         while !self.window.as_ref().unwrap().should_close(){
-            self.glfw.poll_events();
-            self.draw_frame();
+            self.glfw.as_mut().unwrap().poll_events();
+
+            let mut screenshot_requested = false;
+            let mut wireframe_toggled = false;
+            for (_, event) in glfw::flush_messages(self.event.as_ref().unwrap()) {
+                match event {
+                    glfw::WindowEvent::Key(glfw::Key::F12, _, glfw::Action::Press, _) => screenshot_requested = true,
+                    glfw::WindowEvent::Key(glfw::Key::F1, _, glfw::Action::Press, _) => wireframe_toggled = !wireframe_toggled,
+                    glfw::WindowEvent::FramebufferSize(_, _) => self.framebuffer_resized = true,
+                    _ => self.camera.handle_event(self.window.as_mut().unwrap(), &event)
+                }
+            }
+
+            let now = self.glfw.as_ref().unwrap().get_time();
+            let delta_time = now - self.last_frame_time.unwrap_or(now);
+            self.last_frame_time = Some(now);
+            self.camera.update(delta_time as f32);
+
+            if wireframe_toggled {
+                self.toggle_wireframe()?;
+            }
+
+            let changed_shaders = self.shader_watcher.as_mut().unwrap().poll();
+            if !changed_shaders.is_empty() {
+                self.reload_shaders(&changed_shaders)?;
+            }
+
+            if screenshot_requested {
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap().as_secs();
+                self.pending_screenshot = Some(format!("screenshot-{}.png", timestamp).into());
+            }
+
+            self.draw_frame()?;
         }
 
         unsafe{
             self.device.as_ref().unwrap()
             .device_wait_idle()
-            .expect("");
+            .context("failed to wait for device idle")
         }
     }
 }
 
 impl Drop for HelloTriangleApplication {
     fn drop(&mut self) {
+        // nothing may be destroyed while the GPU could still be using it
+        if let Some(device_ref) = self.device.as_ref() {
+            unsafe{ let _ = device_ref.device_wait_idle(); }
+        }
+
+        // initialization may have failed part way, so every handle is released only if it was created
         self.clean_swap_chain();
+        self.destroy_pipelines();
 
         unsafe{
-            let device_ref = self.device.as_ref().unwrap();
+            if let Some(device_ref) = self.device.as_ref() {
+                // every pipeline that will ever be created from the cache has been by now
+                if let Some(mut pipeline_cache) = self.pipeline_cache.take() {
+                    pipeline_cache.save();
+                    pipeline_cache.destroy();
+                }
 
-            device_ref.destroy_descriptor_set_layout(*self.descriptor_set_layout.as_ref().unwrap(), None);
-            self.descriptor_set_layout = None;
+                if let Some(texture_sampler) = self.texture_sampler.take() {
+                    device_ref.destroy_sampler(texture_sampler, None);
+                }
+                for buffer in self.uniform_buffers.drain(..) {
+                    device_ref.destroy_buffer(buffer, None);
+                }
 
-            device_ref.destroy_buffer(*self.vertex_buffer.as_ref().unwrap(), None);
-            self.vertex_buffer = None;
-            device_ref.free_memory(*self.vertex_buffer_memory.as_ref().unwrap(), None);
-            self.vertex_buffer_memory = None;
+                for allocation in self.uniform_buffers_allocations.drain(..) {
+                    self.allocator.as_ref().unwrap().free(allocation);
+                }
 
-            device_ref.destroy_buffer(*self.index_buffer.as_ref().unwrap(), None);
-            self.index_buffer = None;
-            device_ref.free_memory(*self.index_buffer_memory.as_ref().unwrap(), None);
-            self.index_buffer_memory = None;
+                // destroying the pool frees every set allocated from it
+                if let Some(descriptor_pool) = self.descriptor_pool.take() {
+                    device_ref.destroy_descriptor_pool(descriptor_pool, None);
+                }
+                self.descriptor_sets.clear();
 
-            for semaphore in self.render_finished_semaphores.drain(..){
-                device_ref.destroy_semaphore(semaphore, None);
-            }
+                for texture in self.textures.drain(..) {
+                    device_ref.destroy_image_view(texture.view, None);
+                    device_ref.destroy_image(texture.image, None);
+                    self.allocator.as_ref().unwrap().free(texture.allocation);
+                }
+
+                if let Some(descriptor_set_layout) = self.descriptor_set_layout.take() {
+                    device_ref.destroy_descriptor_set_layout(descriptor_set_layout, None);
+                }
+
+                for primitive in self.primitives.drain(..) {
+                    device_ref.destroy_buffer(primitive.vertex_buffer, None);
+                    self.allocator.as_ref().unwrap().free(primitive.vertex_buffer_allocation);
+                    device_ref.destroy_buffer(primitive.index_buffer, None);
+                    self.allocator.as_ref().unwrap().free(primitive.index_buffer_allocation);
+                }
+
+                for semaphore in self.render_finished_semaphores.drain(..){
+                    device_ref.destroy_semaphore(semaphore, None);
+                }
+
+                for semaphore in self.image_available_semaphores.drain(..){
+                    device_ref.destroy_semaphore(semaphore, None);
+                }
+
+                for fence in self.in_flight_fences.drain(..) {
+                    device_ref.destroy_fence(fence, None);
+                }
+
+                if let Some(mut upload_context) = self.upload_context.take() {
+                    upload_context.destroy(self.allocator.as_ref().unwrap());
+                }
 
-            for semaphore in self.image_available_semaphores.drain(..){
-                device_ref.destroy_semaphore(semaphore, None);
+                // every buffer and image has been released above, so only the empty blocks remain
+                if let Some(mut allocator) = self.allocator.take() {
+                    log::debug!("{}", allocator.stats());
+                    allocator.destroy();
+                }
+
+                if let Some(command_pool) = self.command_pool.take() {
+                    device_ref.destroy_command_pool(command_pool, None);
+                }
+
+                // destroying a pool frees the command buffers allocated from it
+                for command_pool in self.frame_command_pools.drain(..) {
+                    device_ref.destroy_command_pool(command_pool, None);
+                }
+                self.frame_command_buffers.clear();
+
+                device_ref.destroy_device(None);
             }
+            self.device = None;
+
+            if let Some(instance) = self.instance.take() {
+                if let Some(surface_handle) = self.surface.take() {
+                    let surface = ash::extensions::khr::Surface::new(&self.vk_entry, &instance);
+                    surface.destroy_surface(surface_handle, None);
+                }
 
-            for fence in self.in_flight_fences.drain(..) {
-                device_ref.destroy_fence(fence, None);
+                if let (Some(debug_utils), Some(debug_messenger)) = (self.debug_utils.take(), self.debug_messenger.take()) {
+                    debug_utils.destroy_debug_utils_messenger(debug_messenger, None);
+                }
+
+                instance.destroy_instance(None);
             }
+        }
+    }
+}
+
+/// The two textured quads drawn when no model is given.
+fn default_geometry() -> (Vec<Vertex>, Vec<u32>) {
+    let normal = [0.0, 0.0, 1.0];
+    let vertices = vec![
+        Vertex{pos: [-0.5, -0.5, 0.0], color: [1.0, 0.0, 0.0], tex_coord: [1.0, 0.0], normal: normal},
+        Vertex{pos: [0.5, -0.5, 0.0], color: [0.0, 1.0, 0.0], tex_coord: [0.0, 0.0], normal: normal},
+        Vertex{pos: [0.5, 0.5, 0.0], color: [0.0, 0.0, 1.0], tex_coord: [0.0, 1.0], normal: normal},
+        Vertex{pos: [-0.5, 0.5, 0.0], color: [1.0, 1.0, 1.0], tex_coord: [1.0, 1.0], normal: normal},
+
+        Vertex{pos: [-0.5, -0.5, -0.5], color: [1.0, 0.0, 0.0], tex_coord: [1.0, 0.0], normal: normal},
+        Vertex{pos: [0.5, -0.5, -0.5], color: [0.0, 1.0, 0.0], tex_coord: [0.0, 0.0], normal: normal},
+        Vertex{pos: [0.5, 0.5, -0.5], color: [0.0, 0.0, 1.0], tex_coord: [0.0, 1.0], normal: normal},
+        Vertex{pos: [-0.5, 0.5, -0.5], color: [1.0, 1.0, 1.0], tex_coord: [1.0, 1.0], normal: normal},
+    ];
+    let indices = vec![
+        0, 1, 2, 2, 3, 0,
+        4, 5, 6, 6, 7, 4
+    ];
+    (vertices, indices)
+}
 
-            device_ref.destroy_command_pool(*self.command_pool.as_ref().unwrap(), None);
-            self.command_pool = None;
+fn has_extension(available_extensions : &[vk::ExtensionProperties], name : &std::ffi::CStr) -> bool {
+    available_extensions.iter().any(|extension| {
+        name == unsafe{ std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()) }
+    })
+}
 
-            device_ref.destroy_device(None);
+fn has_layer(available_layers : &[vk::LayerProperties], name : &std::ffi::CStr) -> bool {
+    available_layers.iter().any(|layer| {
+        name == unsafe{ std::ffi::CStr::from_ptr(layer.layer_name.as_ptr()) }
+    })
+}
 
-            let surface = ash::extensions::khr::Surface::new(&self.vk_entry, self.instance.as_ref().unwrap());
-            surface.destroy_surface(*self.surface.as_ref().unwrap(), None);
-            self.surface = None;
+/// Device type rank, device-local memory in bytes, then the largest 2D image dimension; compared in that order.
+type DeviceScore = (u32, vk::DeviceSize, u32);
+
+fn score_device(properties : &vk::PhysicalDeviceProperties, memory_properties : &vk::PhysicalDeviceMemoryProperties) -> DeviceScore {
+    let type_rank = match properties.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0
+    };
+
+    let device_local_memory = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+
+    (type_rank, device_local_memory, properties.limits.max_image_dimension2_d)
+}
 
-            self.instance.as_ref().unwrap().destroy_instance(None);
-            self.instance = None;
-        }
+fn device_name(properties : &vk::PhysicalDeviceProperties) -> String {
+    unsafe{ std::ffi::CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+fn format_uuid(uuid : &[u8; vk::UUID_SIZE]) -> String {
+    let hex : Vec<String> = uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", hex[0..4].concat(), hex[4..6].concat(), hex[6..8].concat(), hex[8..10].concat(), hex[10..16].concat())
+}
+
+fn populate_debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
+    vk::DebugUtilsMessengerCreateInfoEXT {
+        s_type : vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+        p_next : std::ptr::null(),
+        flags : vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
+        message_severity :
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+            | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+            | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        message_type :
+            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        pfn_user_callback : Some(vulkan_debug_callback),
+        p_user_data : std::ptr::null_mut()
+    }
+}
+
+/// Forwards debug-utils messages to the `log` crate, mapping the Vulkan severity to a log level.
+unsafe extern "system" fn vulkan_debug_callback(
+    message_severity : vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type : vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data : *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _p_user_data : *mut std::ffi::c_void
+) -> vk::Bool32 {
+    let message = match p_callback_data.is_null() || (*p_callback_data).p_message.is_null() {
+        true => std::borrow::Cow::from("<no message>"),
+        false => std::ffi::CStr::from_ptr((*p_callback_data).p_message).to_string_lossy()
+    };
+
+    let level = if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::Level::Error
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::Level::Warn
+    } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::Level::Info
+    } else {
+        log::Level::Trace
+    };
+
+    log::log!(target: "vulkan", level, "[{:?}] {}", message_type, message);
+
+    vk::FALSE
+}
+
+/// Surface extension of the window system `create_platform_surface` falls back to, if it has one.
+#[cfg(target_os = "windows")]
+fn platform_surface_extension_name() -> Option<&'static std::ffi::CStr> {
+    Some(ash::extensions::khr::Win32Surface::name())
+}
+
+/// The fallback only speaks Xlib, which is useless without an X server (XWayland included) to talk to.
+#[cfg(target_os = "linux")]
+fn platform_surface_extension_name() -> Option<&'static std::ffi::CStr> {
+    std::env::var_os("DISPLAY")
+        .filter(|display| !display.is_empty())
+        .map(|_| ash::extensions::khr::XlibSurface::name())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn platform_surface_extension_name() -> Option<&'static std::ffi::CStr> {
+    None
+}
+
+/// Reorders tightly packed 4-byte pixels of `format` into RGBA8 in place.
+fn convert_to_rgba8(format : vk::Format, pixels : &mut [u8]) -> Result<(), RendererError> {
+    match format {
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            Ok(())
+        },
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => Ok(()),
+        _ => Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED).context("cannot convert image to RGBA8")
     }
 }
 
-fn read_file(file_name : &std::path::Path) -> Vec<u8>{
-    let mut file = std::fs::File::open(file_name).expect("failed to open file");
+fn write_png(file_name : &std::path::Path, width : u32, height : u32, rgba : &[u8]) -> Result<(), RendererError> {
+    let image_write_error = |message : String| RendererError::ImageWrite {
+        path : file_name.to_owned(),
+        message : message
+    };
+
+    let file = std::fs::File::create(file_name).map_err(|error| image_write_error(error.to_string()))?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|error| image_write_error(error.to_string()))?;
+    writer.write_image_data(rgba).map_err(|error| image_write_error(error.to_string()))
+}
+
+fn read_file(file_name : &std::path::Path) -> std::io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(file_name)?;
     let mut buffer = Vec::<u8>::new();
-    file.read_to_end(&mut buffer).expect("failed to open file");
-    return buffer;
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
 
+/// Loads the SPIR-V code of a shader. `.spv` files are read as they are, after checking their magic number
+/// and word alignment; any other file is compiled from source by `shader_compiler::compile`.
+fn load_shader(file_name : &std::path::Path, stage : vk::ShaderStageFlags) -> Result<Vec<u32>, RendererError> {
+    if file_name.extension().is_none_or(|extension| extension != "spv") {
+        return shader_compiler::compile(file_name, stage);
+    }
+
+    read_file(file_name)
+        .and_then(|bytes| ash::util::read_spv(&mut std::io::Cursor::new(bytes)))
+        .map_err(|error| RendererError::ShaderLoad {
+            path : file_name.to_owned(),
+            source : error
+        })
 }
 
 
+/// Per-user directory for the pipeline cache and compiled shaders, if the platform has one.
+fn cache_directory() -> Option<std::path::PathBuf> {
+    dirs::cache_dir().map(|directory| directory.join("rust-vulkan-tutorial"))
+}
+
 fn main() {
-    let mut app = HelloTriangleApplication::new();
-    app.run();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let result = AppOptions::from_args(std::env::args().skip(1))
+        .and_then(HelloTriangleApplication::new)
+        .and_then(|mut app| app.run());
+
+    if let Err(error) = result {
+        log::error!("{}", error);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,334 @@
+use ash::vk;
+use crate::error::{RendererError, VkResultExt};
+
+/// Plain data that can be recorded with `push_constants`.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` without padding, as values are copied into the command buffer byte by byte
+/// and have to match the layout of the shader's push constant block.
+pub unsafe trait PushConstants : Copy {}
+
+/// Records `value` at offset 0 of the push constant range of `layout`. `stage_flags` has to name every stage
+/// whose range overlaps `T`.
+pub fn push_constants<T : PushConstants>(
+    device : &ash::Device,
+    command_buffer : vk::CommandBuffer,
+    layout : vk::PipelineLayout,
+    stage_flags : vk::ShaderStageFlags,
+    value : &T
+) {
+    unsafe{
+        let bytes = std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>());
+        device.cmd_push_constants(command_buffer, layout, stage_flags, 0, bytes);
+    }
+}
+
+/// Writes the source color as is.
+pub fn opaque_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        color_write_mask : vk::ColorComponentFlags::all(),
+        blend_enable : vk::FALSE,
+        src_color_blend_factor : vk::BlendFactor::ONE,
+        dst_color_blend_factor : vk::BlendFactor::ZERO,
+        color_blend_op : vk::BlendOp::ADD,
+        src_alpha_blend_factor : vk::BlendFactor::ONE,
+        dst_alpha_blend_factor : vk::BlendFactor::ZERO,
+        alpha_blend_op : vk::BlendOp::ADD
+    }
+}
+
+/// Classic non-premultiplied "over" blending.
+pub fn alpha_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        color_write_mask : vk::ColorComponentFlags::all(),
+        blend_enable : vk::TRUE,
+        src_color_blend_factor : vk::BlendFactor::SRC_ALPHA,
+        dst_color_blend_factor : vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        color_blend_op : vk::BlendOp::ADD,
+        src_alpha_blend_factor : vk::BlendFactor::ONE,
+        dst_alpha_blend_factor : vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        alpha_blend_op : vk::BlendOp::ADD
+    }
+}
+
+/// Collects the fixed function state of a graphics pipeline through chained setters.
+///
+/// The defaults describe an opaque, back face culled triangle list with depth testing and a single color
+/// attachment. Viewport and scissor are always dynamic, so a pipeline outlives the swap chain extent it was
+/// first used with. Shader modules and the layout are borrowed and must outlive `build`.
+#[derive(Clone)]
+pub struct GraphicsPipelineBuilder {
+    stages : Vec<(vk::ShaderStageFlags, vk::ShaderModule)>,
+    vertex_bindings : Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes : Vec<vk::VertexInputAttributeDescription>,
+    topology : vk::PrimitiveTopology,
+    primitive_restart : bool,
+    polygon_mode : vk::PolygonMode,
+    cull_mode : vk::CullModeFlags,
+    front_face : vk::FrontFace,
+    line_width : f32,
+    depth_test : bool,
+    depth_write : bool,
+    depth_compare_op : vk::CompareOp,
+    blend_attachments : Vec<vk::PipelineColorBlendAttachmentState>,
+    /// Always starts with VIEWPORT and SCISSOR.
+    dynamic_states : Vec<vk::DynamicState>,
+    layout : vk::PipelineLayout,
+    render_pass : vk::RenderPass,
+    subpass : u32
+}
+
+impl GraphicsPipelineBuilder {
+    pub fn new() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder {
+            stages : Vec::new(),
+            vertex_bindings : Vec::new(),
+            vertex_attributes : Vec::new(),
+            topology : vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart : false,
+            polygon_mode : vk::PolygonMode::FILL,
+            cull_mode : vk::CullModeFlags::BACK,
+            front_face : vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width : 1.0,
+            depth_test : true,
+            depth_write : true,
+            depth_compare_op : vk::CompareOp::LESS,
+            blend_attachments : vec![opaque_blend_attachment()],
+            dynamic_states : vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            layout : vk::PipelineLayout::null(),
+            render_pass : vk::RenderPass::null(),
+            subpass : 0
+        }
+    }
+
+    /// Adds a stage whose entry point is `main`.
+    pub fn shader(mut self, stage : vk::ShaderStageFlags, module : vk::ShaderModule) -> Self {
+        self.stages.push((stage, module));
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings : &[vk::VertexInputBindingDescription],
+        attributes : &[vk::VertexInputAttributeDescription]
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    pub fn topology(mut self, topology : vk::PrimitiveTopology, primitive_restart : bool) -> Self {
+        self.topology = topology;
+        self.primitive_restart = primitive_restart;
+        self
+    }
+
+    /// `PolygonMode::LINE` and `POINT` need the `fillModeNonSolid` device feature.
+    pub fn polygon_mode(mut self, polygon_mode : vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode : vk::CullModeFlags, front_face : vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    /// Widths other than 1.0 need the `wideLines` device feature.
+    pub fn line_width(mut self, line_width : f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn depth(mut self, test : bool, write : bool, compare_op : vk::CompareOp) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    /// Blend state for every color attachment of the subpass, in attachment order.
+    pub fn blend_attachments(mut self, attachments : &[vk::PipelineColorBlendAttachmentState]) -> Self {
+        self.blend_attachments = attachments.to_vec();
+        self
+    }
+
+    /// Dynamic state in addition to the viewport and scissor, which are always dynamic. States that are
+    /// already dynamic are not added twice.
+    #[allow(dead_code)] // none of the scene pipelines needs more than the viewport and scissor yet
+    pub fn dynamic_states(mut self, dynamic_states : &[vk::DynamicState]) -> Self {
+        for &dynamic_state in dynamic_states {
+            if !self.dynamic_states.contains(&dynamic_state) {
+                self.dynamic_states.push(dynamic_state);
+            }
+        }
+        self
+    }
+
+    pub fn layout(mut self, layout : vk::PipelineLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn render_pass(mut self, render_pass : vk::RenderPass, subpass : u32) -> Self {
+        self.render_pass = render_pass;
+        self.subpass = subpass;
+        self
+    }
+
+    pub fn build(&self, device : &ash::Device, pipeline_cache : vk::PipelineCache) -> Result<vk::Pipeline, RendererError> {
+        let name = std::ffi::CString::new("main").unwrap();
+        let shader_stages : Vec<vk::PipelineShaderStageCreateInfo> = self.stages.iter().map(|(stage, module)| {
+            vk::PipelineShaderStageCreateInfo {
+                s_type : vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                p_next : std::ptr::null(),
+                stage : *stage,
+                module : *module,
+                p_name : name.as_ptr(),
+                flags : vk::PipelineShaderStageCreateFlags::empty(),
+                p_specialization_info : std::ptr::null()
+            }
+        }).collect();
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            vertex_binding_description_count : self.vertex_bindings.len() as u32,
+            p_vertex_binding_descriptions : self.vertex_bindings.as_ptr(),
+            vertex_attribute_description_count : self.vertex_attributes.len() as u32,
+            p_vertex_attribute_descriptions : self.vertex_attributes.as_ptr(),
+            flags : vk::PipelineVertexInputStateCreateFlags::empty()
+        };
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            topology : self.topology,
+            primitive_restart_enable : self.primitive_restart as vk::Bool32,
+            flags : vk::PipelineInputAssemblyStateCreateFlags::empty()
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            // both are set with cmd_set_viewport and cmd_set_scissor while recording
+            viewport_count : 1,
+            p_viewports : std::ptr::null(),
+            scissor_count : 1,
+            p_scissors : std::ptr::null(),
+            flags : vk::PipelineViewportStateCreateFlags::empty()
+        };
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            depth_clamp_enable : vk::FALSE,
+            rasterizer_discard_enable : vk::FALSE,
+            polygon_mode : self.polygon_mode,
+            line_width : self.line_width,
+            cull_mode : self.cull_mode,
+            front_face : self.front_face,
+            depth_bias_enable : vk::FALSE,
+            depth_bias_constant_factor : 0.0,
+            depth_bias_clamp : 0.0,
+            depth_bias_slope_factor : 0.0,
+            flags : vk::PipelineRasterizationStateCreateFlags::empty()
+        };
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            sample_shading_enable : vk::FALSE,
+            rasterization_samples : vk::SampleCountFlags::TYPE_1,
+            min_sample_shading : 1.0,
+            p_sample_mask : std::ptr::null(),
+            alpha_to_coverage_enable : vk::FALSE,
+            alpha_to_one_enable : vk::FALSE,
+            flags : vk::PipelineMultisampleStateCreateFlags::empty()
+        };
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            depth_test_enable : self.depth_test as vk::Bool32,
+            depth_write_enable : self.depth_write as vk::Bool32,
+            depth_compare_op : self.depth_compare_op,
+            depth_bounds_test_enable : vk::FALSE,
+            stencil_test_enable : vk::FALSE,
+            front : vk::StencilOpState::default(),
+            back : vk::StencilOpState::default(),
+            min_depth_bounds : 0.0,
+            max_depth_bounds : 1.0,
+            flags : vk::PipelineDepthStencilStateCreateFlags::empty()
+        };
+
+        let color_blending = vk::PipelineColorBlendStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            logic_op_enable : vk::FALSE,
+            logic_op : vk::LogicOp::COPY,
+            attachment_count : self.blend_attachments.len() as u32,
+            p_attachments : self.blend_attachments.as_ptr(),
+            blend_constants : [0.0, 0.0, 0.0, 0.0],
+            flags : vk::PipelineColorBlendStateCreateFlags::empty()
+        };
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            s_type : vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            dynamic_state_count : self.dynamic_states.len() as u32,
+            p_dynamic_states : self.dynamic_states.as_ptr(),
+            flags : vk::PipelineDynamicStateCreateFlags::empty()
+        };
+
+        let pipeline_info = [vk::GraphicsPipelineCreateInfo {
+            s_type : vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            stage_count : shader_stages.len() as u32,
+            p_stages : shader_stages.as_ptr(),
+            p_vertex_input_state : &vertex_input_info,
+            p_input_assembly_state : &input_assembly,
+            p_viewport_state : &viewport_state,
+            p_rasterization_state : &rasterizer,
+            p_multisample_state : &multisampling,
+            p_depth_stencil_state : &depth_stencil,
+            p_color_blend_state : &color_blending,
+            p_dynamic_state : &dynamic_state,
+            layout : self.layout,
+            render_pass : self.render_pass,
+            subpass : self.subpass,
+            base_pipeline_handle : vk::Pipeline::null(),
+            base_pipeline_index : -1,
+            flags : vk::PipelineCreateFlags::empty(),
+            p_tessellation_state : std::ptr::null()
+        }];
+
+        let pipelines = unsafe{
+            device.create_graphics_pipelines(pipeline_cache, &pipeline_info, None)
+            .map_err(|(_, result)| result)
+            .context("failed to create graphics pipeline!")?
+        };
+        Ok(pipelines[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dynamic_states_extend_viewport_and_scissor_without_duplicates() {
+        let builder = GraphicsPipelineBuilder::new()
+            .dynamic_states(&[vk::DynamicState::LINE_WIDTH, vk::DynamicState::SCISSOR])
+            .dynamic_states(&[vk::DynamicState::LINE_WIDTH, vk::DynamicState::DEPTH_BIAS]);
+
+        assert_eq!(builder.dynamic_states, vec![
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR,
+            vk::DynamicState::LINE_WIDTH,
+            vk::DynamicState::DEPTH_BIAS
+        ]);
+    }
+}
@@ -0,0 +1,148 @@
+use ash::vk;
+use crate::error::{RendererError, VkResultExt};
+
+/// Size of a `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header.
+const HEADER_SIZE : usize = 32;
+
+/// A `vk::PipelineCache` that is loaded from and saved to the per-user cache directory.
+///
+/// Cache data is only reused when its header names the same vendor, device and `pipeline_cache_uuid` as the
+/// physical device, which changes with every driver update. Anything else is dropped and the cache starts
+/// out empty, so a stale or corrupt file never costs more than a cold start.
+pub struct PipelineCache {
+    device : ash::Device,
+    cache : vk::PipelineCache,
+    path : Option<std::path::PathBuf>,
+    properties : vk::PhysicalDeviceProperties
+}
+
+impl PipelineCache {
+    pub fn new(
+        instance : &ash::Instance,
+        physical_device : vk::PhysicalDevice,
+        device : &ash::Device
+    ) -> Result<PipelineCache, RendererError> {
+        let properties = unsafe{ instance.get_physical_device_properties(physical_device) };
+        let path = crate::cache_directory().map(|directory| {
+            directory.join(format!("pipelines-{:04x}-{:04x}.bin", properties.vendor_id, properties.device_id))
+        });
+
+        let initial_data = match path.as_ref() {
+            Some(path) => match std::fs::read(path) {
+                Ok(data) if header_matches(&data, &properties) => {
+                    log::info!("loaded {} bytes of pipeline cache from {}", data.len(), path.display());
+                    data
+                },
+                Ok(_) => {
+                    log::info!("discarding pipeline cache {} built for another device or driver", path.display());
+                    Vec::new()
+                },
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(error) => {
+                    log::warn!("failed to read pipeline cache {}: {}", path.display(), error);
+                    Vec::new()
+                }
+            },
+            None => {
+                log::warn!("no cache directory, pipelines will not be cached between runs");
+                Vec::new()
+            }
+        };
+
+        let cache = match create_pipeline_cache(device, &initial_data) {
+            Ok(cache) => cache,
+            // the header looked right but the driver still refused the data
+            Err(_) if !initial_data.is_empty() => {
+                log::warn!("driver rejected the pipeline cache, starting with an empty one");
+                create_pipeline_cache(device, &[])?
+            },
+            Err(error) => return Err(error)
+        };
+
+        Ok(PipelineCache {
+            device : device.clone(),
+            cache,
+            path,
+            properties
+        })
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    /// Writes the cache to a temporary file next to its final location and renames it into place, so a crash
+    /// half way never leaves a truncated cache behind. Failures are logged, the cache is only an optimization.
+    pub fn save(&self) {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return
+        };
+
+        let data = match unsafe{ self.device.get_pipeline_cache_data(self.cache) } {
+            Ok(data) => data,
+            Err(result) => {
+                log::warn!("failed to get pipeline cache data: {}", result);
+                return;
+            }
+        };
+        if !header_matches(&data, &self.properties) {
+            log::warn!("driver returned pipeline cache data without a valid header, not saving it");
+            return;
+        }
+
+        let temporary_path = path.with_extension("tmp");
+        let result = path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&temporary_path, &data))
+            .and_then(|_| std::fs::rename(&temporary_path, path));
+
+        match result {
+            Ok(()) => log::info!("saved {} bytes of pipeline cache to {}", data.len(), path.display()),
+            Err(error) => {
+                log::warn!("failed to save pipeline cache {}: {}", path.display(), error);
+                let _ = std::fs::remove_file(&temporary_path);
+            }
+        }
+    }
+
+    /// Must not be called while a pipeline is being created from the cache.
+    pub fn destroy(&mut self) {
+        unsafe{ self.device.destroy_pipeline_cache(self.cache, None); }
+        self.cache = vk::PipelineCache::null();
+    }
+}
+
+fn create_pipeline_cache(device : &ash::Device, initial_data : &[u8]) -> Result<vk::PipelineCache, RendererError> {
+    let cache_info = vk::PipelineCacheCreateInfo {
+        s_type : vk::StructureType::PIPELINE_CACHE_CREATE_INFO,
+        p_next : std::ptr::null(),
+        flags : vk::PipelineCacheCreateFlags::empty(),
+        initial_data_size : initial_data.len(),
+        p_initial_data : initial_data.as_ptr() as *const std::ffi::c_void
+    };
+
+    unsafe{
+        device.create_pipeline_cache(&cache_info, None)
+        .context("failed to create pipeline cache!")
+    }
+}
+
+/// Checks the `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header at the start of `data` against the device.
+fn header_matches(data : &[u8], properties : &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+
+    let read_u32 = |offset : usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    };
+
+    read_u32(0) as usize >= HEADER_SIZE
+        && read_u32(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && read_u32(8) == properties.vendor_id
+        && read_u32(12) == properties.device_id
+        && data[16..32] == properties.pipeline_cache_uuid
+}
@@ -0,0 +1,681 @@
+use std::collections::HashMap;
+use ash::vk;
+use crate::error::{RendererError, VkResultExt};
+
+const SPIRV_MAGIC : u32 = 0x07230203;
+
+// the few opcodes, decorations, storage classes and execution models reflection has to understand
+const OP_NAME : u32 = 5;
+const OP_ENTRY_POINT : u32 = 15;
+const OP_TYPE_INT : u32 = 21;
+const OP_TYPE_FLOAT : u32 = 22;
+const OP_TYPE_VECTOR : u32 = 23;
+const OP_TYPE_MATRIX : u32 = 24;
+const OP_TYPE_IMAGE : u32 = 25;
+const OP_TYPE_SAMPLER : u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE : u32 = 27;
+const OP_TYPE_ARRAY : u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY : u32 = 29;
+const OP_TYPE_STRUCT : u32 = 30;
+const OP_TYPE_POINTER : u32 = 32;
+const OP_CONSTANT : u32 = 43;
+const OP_VARIABLE : u32 = 59;
+const OP_DECORATE : u32 = 71;
+const OP_MEMBER_DECORATE : u32 = 72;
+
+const DECORATION_BLOCK : u32 = 2;
+const DECORATION_BUFFER_BLOCK : u32 = 3;
+const DECORATION_ARRAY_STRIDE : u32 = 6;
+const DECORATION_MATRIX_STRIDE : u32 = 7;
+const DECORATION_BUILT_IN : u32 = 11;
+const DECORATION_LOCATION : u32 = 30;
+const DECORATION_BINDING : u32 = 33;
+const DECORATION_DESCRIPTOR_SET : u32 = 34;
+const DECORATION_OFFSET : u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT : u32 = 0;
+const STORAGE_CLASS_INPUT : u32 = 1;
+const STORAGE_CLASS_UNIFORM : u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT : u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER : u32 = 12;
+
+const EXECUTION_MODEL_VERTEX : u32 = 0;
+const EXECUTION_MODEL_FRAGMENT : u32 = 4;
+const EXECUTION_MODEL_GL_COMPUTE : u32 = 5;
+
+const DIM_BUFFER : u32 = 5;
+const DIM_SUBPASS_DATA : u32 = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DescriptorBinding {
+    pub set : u32,
+    pub binding : u32,
+    pub descriptor_type : vk::DescriptorType,
+    pub count : u32,
+    pub stage_flags : vk::ShaderStageFlags,
+    /// Name of the shader variable, for error messages.
+    pub name : String
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexInput {
+    pub location : u32,
+    pub format : vk::Format,
+    pub name : String
+}
+
+/// Resources a single shader stage declares.
+pub struct ShaderReflection {
+    pub stage : vk::ShaderStageFlags,
+    pub descriptor_bindings : Vec<DescriptorBinding>,
+    pub push_constant_range : Option<vk::PushConstantRange>,
+    /// Inputs of a vertex shader that are fed from vertex buffers, sorted by location.
+    pub vertex_inputs : Vec<VertexInput>
+}
+
+/// One SPIR-V type declaration, with its result id stripped.
+enum Type {
+    Int { width : u32, signed : bool },
+    Float { width : u32 },
+    Vector { component : u32, count : u32 },
+    Matrix { column : u32, count : u32 },
+    Image { dim : u32, sampled : u32 },
+    Sampler,
+    SampledImage,
+    Array { element : u32, length : u32 },
+    RuntimeArray,
+    Struct { members : Vec<u32> },
+    Pointer { pointee : u32 }
+}
+
+/// The declarations of a module, indexed by result id.
+#[derive(Default)]
+struct Module {
+    names : HashMap<u32, String>,
+    types : HashMap<u32, Type>,
+    constants : HashMap<u32, u32>,
+    /// (result type, result id, storage class) of every variable.
+    variables : Vec<(u32, u32, u32)>,
+    decorations : HashMap<(u32, u32), u32>,
+    flags : HashMap<u32, Vec<u32>>,
+    member_decorations : HashMap<(u32, u32, u32), u32>,
+    entry_point : Option<(u32, Vec<u32>)>
+}
+
+impl Module {
+    fn parse(code : &[u32]) -> Result<Module, String> {
+        if code.len() < 5 || code[0] != SPIRV_MAGIC {
+            return Err("not a SPIR-V module".to_owned());
+        }
+
+        let mut module = Module::default();
+        let mut position = 5;
+        while position < code.len() {
+            let word_count = (code[position] >> 16) as usize;
+            let opcode = code[position] & 0xffff;
+            if word_count == 0 || position + word_count > code.len() {
+                return Err(format!("truncated instruction at word {}", position));
+            }
+            let operands = &code[position + 1..position + word_count];
+            position += word_count;
+
+            match opcode {
+                OP_NAME => { module.names.insert(operands[0], parse_string(&operands[1..])); },
+                OP_ENTRY_POINT => {
+                    if module.entry_point.is_some() {
+                        return Err("modules with more than one entry point are not supported".to_owned());
+                    }
+                    // the interface ids follow the zero terminated, word padded name
+                    let name_words = operands[2..].iter().position(|word| word >> 24 == 0).map_or(0, |index| index + 1);
+                    module.entry_point = Some((operands[0], operands[2 + name_words..].to_vec()));
+                },
+                OP_TYPE_INT => { module.types.insert(operands[0], Type::Int { width : operands[1], signed : operands[2] == 1 }); },
+                OP_TYPE_FLOAT => { module.types.insert(operands[0], Type::Float { width : operands[1] }); },
+                OP_TYPE_VECTOR => { module.types.insert(operands[0], Type::Vector { component : operands[1], count : operands[2] }); },
+                OP_TYPE_MATRIX => { module.types.insert(operands[0], Type::Matrix { column : operands[1], count : operands[2] }); },
+                OP_TYPE_IMAGE => { module.types.insert(operands[0], Type::Image { dim : operands[2], sampled : operands[6] }); },
+                OP_TYPE_SAMPLER => { module.types.insert(operands[0], Type::Sampler); },
+                OP_TYPE_SAMPLED_IMAGE => { module.types.insert(operands[0], Type::SampledImage); },
+                OP_TYPE_ARRAY => { module.types.insert(operands[0], Type::Array { element : operands[1], length : operands[2] }); },
+                OP_TYPE_RUNTIME_ARRAY => { module.types.insert(operands[0], Type::RuntimeArray); },
+                OP_TYPE_STRUCT => { module.types.insert(operands[0], Type::Struct { members : operands[1..].to_vec() }); },
+                OP_TYPE_POINTER => { module.types.insert(operands[0], Type::Pointer { pointee : operands[2] }); },
+                // only 32 bit constants are needed, as array lengths
+                OP_CONSTANT if operands.len() == 3 => { module.constants.insert(operands[1], operands[2]); },
+                OP_VARIABLE => module.variables.push((operands[0], operands[1], operands[2])),
+                OP_DECORATE => match operands.get(2) {
+                    Some(&value) => { module.decorations.insert((operands[0], operands[1]), value); },
+                    None => module.flags.entry(operands[0]).or_default().push(operands[1])
+                },
+                OP_MEMBER_DECORATE => if let Some(&value) = operands.get(3) {
+                    module.member_decorations.insert((operands[0], operands[1], operands[2]), value);
+                },
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn name(&self, id : u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_else(|| format!("%{}", id))
+    }
+
+    fn get_type(&self, id : u32) -> Result<&Type, String> {
+        self.types.get(&id).ok_or_else(|| format!("undeclared type %{}", id))
+    }
+
+    fn pointee(&self, pointer : u32) -> Result<u32, String> {
+        match self.get_type(pointer)? {
+            Type::Pointer { pointee } => Ok(*pointee),
+            _ => Err(format!("variable type %{} is not a pointer", pointer))
+        }
+    }
+
+    fn descriptor_binding(&self, variable : u32, pointer : u32, storage_class : u32) -> Result<(vk::DescriptorType, u32), String> {
+        let mut type_id = self.pointee(pointer)?;
+        let mut count = 1;
+        if let Type::Array { element, length } = self.get_type(type_id)? {
+            count = *self.constants.get(length).ok_or_else(|| format!("array length of {} is not a constant", self.name(variable)))?;
+            type_id = *element;
+        }
+
+        let has_flag = |flag| self.flags.get(&type_id).is_some_and(|flags| flags.contains(&flag));
+        let descriptor_type = match (storage_class, self.get_type(type_id)?) {
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::Image { dim, sampled }) => match (*dim, *sampled) {
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE
+            },
+            (STORAGE_CLASS_UNIFORM, Type::Struct { .. }) if has_flag(DECORATION_BUFFER_BLOCK) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_CLASS_UNIFORM, Type::Struct { .. }) if has_flag(DECORATION_BLOCK) => vk::DescriptorType::UNIFORM_BUFFER,
+            (STORAGE_CLASS_STORAGE_BUFFER, Type::Struct { .. }) => vk::DescriptorType::STORAGE_BUFFER,
+            (_, Type::RuntimeArray) => return Err(format!("runtime sized descriptor array {} is not supported", self.name(variable))),
+            _ => return Err(format!("unsupported resource type of {}", self.name(variable)))
+        };
+        Ok((descriptor_type, count))
+    }
+
+    /// Size in bytes of a value of type `type_id`. `matrix_stride` comes from the enclosing struct member.
+    fn size_of(&self, type_id : u32, matrix_stride : Option<u32>) -> Result<u32, String> {
+        Ok(match self.get_type(type_id)? {
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            Type::Vector { component, count } => self.size_of(*component, None)? * count,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => stride * count,
+                None => self.size_of(*column, None)? * count
+            },
+            Type::Array { element, length } => {
+                let length = *self.constants.get(length).ok_or_else(|| "array length is not a constant".to_owned())?;
+                match self.decorations.get(&(type_id, DECORATION_ARRAY_STRIDE)) {
+                    Some(stride) => stride * length,
+                    None => self.size_of(*element, None)? * length
+                }
+            },
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (index, member) in members.iter().enumerate() {
+                    let index = index as u32;
+                    let offset = self.member_decorations.get(&(type_id, index, DECORATION_OFFSET)).copied().unwrap_or(size);
+                    let stride = self.member_decorations.get(&(type_id, index, DECORATION_MATRIX_STRIDE)).copied();
+                    size = size.max(offset + self.size_of(*member, stride)?);
+                }
+                size
+            },
+            _ => return Err(format!("type %{} has no size", type_id))
+        })
+    }
+
+    fn vertex_format(&self, type_id : u32) -> Option<vk::Format> {
+        let (component, count) = match self.types.get(&type_id)? {
+            Type::Vector { component, count } => (*component, *count),
+            _ => (type_id, 1)
+        };
+
+        let formats = match self.types.get(&component)? {
+            Type::Float { width : 32 } => [vk::Format::R32_SFLOAT, vk::Format::R32G32_SFLOAT, vk::Format::R32G32B32_SFLOAT, vk::Format::R32G32B32A32_SFLOAT],
+            Type::Int { width : 32, signed : true } => [vk::Format::R32_SINT, vk::Format::R32G32_SINT, vk::Format::R32G32B32_SINT, vk::Format::R32G32B32A32_SINT],
+            Type::Int { width : 32, signed : false } => [vk::Format::R32_UINT, vk::Format::R32G32_UINT, vk::Format::R32G32B32_UINT, vk::Format::R32G32B32A32_UINT],
+            _ => return None
+        };
+        formats.get(count as usize - 1).copied()
+    }
+}
+
+impl ShaderReflection {
+    /// Extracts the descriptor bindings, push constant block and vertex inputs of a single entry point module.
+    pub fn reflect(code : &[u32]) -> Result<ShaderReflection, String> {
+        let module = Module::parse(code)?;
+        let (execution_model, interface) = module.entry_point.clone().ok_or_else(|| "no entry point".to_owned())?;
+        let stage = match execution_model {
+            EXECUTION_MODEL_VERTEX => vk::ShaderStageFlags::VERTEX,
+            EXECUTION_MODEL_FRAGMENT => vk::ShaderStageFlags::FRAGMENT,
+            EXECUTION_MODEL_GL_COMPUTE => vk::ShaderStageFlags::COMPUTE,
+            _ => return Err(format!("unsupported execution model {}", execution_model))
+        };
+
+        let mut reflection = ShaderReflection {
+            stage,
+            descriptor_bindings : Vec::new(),
+            push_constant_range : None,
+            vertex_inputs : Vec::new()
+        };
+
+        for &(pointer, variable, storage_class) in module.variables.iter() {
+            match storage_class {
+                STORAGE_CLASS_UNIFORM_CONSTANT | STORAGE_CLASS_UNIFORM | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let binding = module.decorations.get(&(variable, DECORATION_BINDING))
+                        .ok_or_else(|| format!("{} has no binding", module.name(variable)))?;
+                    let (descriptor_type, count) = module.descriptor_binding(variable, pointer, storage_class)?;
+                    reflection.descriptor_bindings.push(DescriptorBinding {
+                        set : module.decorations.get(&(variable, DECORATION_DESCRIPTOR_SET)).copied().unwrap_or(0),
+                        binding : *binding,
+                        descriptor_type,
+                        count,
+                        stage_flags : stage,
+                        name : module.name(variable)
+                    });
+                },
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    reflection.push_constant_range = Some(vk::PushConstantRange {
+                        stage_flags : stage,
+                        offset : 0,
+                        size : module.size_of(module.pointee(pointer)?, None)?
+                    });
+                },
+                STORAGE_CLASS_INPUT if stage == vk::ShaderStageFlags::VERTEX && interface.contains(&variable) => {
+                    let type_id = module.pointee(pointer)?;
+                    // gl_VertexIndex and friends, also when wrapped in a block
+                    let is_built_in = module.decorations.contains_key(&(variable, DECORATION_BUILT_IN))
+                        || module.member_decorations.keys().any(|&(id, _, decoration)| id == type_id && decoration == DECORATION_BUILT_IN);
+                    if is_built_in {
+                        continue;
+                    }
+
+                    let name = module.name(variable);
+                    let location = *module.decorations.get(&(variable, DECORATION_LOCATION))
+                        .ok_or_else(|| format!("vertex input {} has no location", name))?;
+                    let format = module.vertex_format(type_id)
+                        .ok_or_else(|| format!("vertex input {} has a type that cannot be fed from a vertex buffer", name))?;
+                    reflection.vertex_inputs.push(VertexInput { location, format, name });
+                },
+                _ => {}
+            }
+        }
+
+        reflection.descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        reflection.vertex_inputs.sort_by_key(|input| input.location);
+        Ok(reflection)
+    }
+}
+
+/// The combined resource interface of all stages of a pipeline.
+#[derive(Clone, Debug)]
+pub struct PipelineInterface {
+    /// Sorted by set and binding, with the stage flags of every stage that uses the binding.
+    pub descriptor_bindings : Vec<DescriptorBinding>,
+    /// One range per stage that declares a push constant block.
+    pub push_constant_ranges : Vec<vk::PushConstantRange>,
+    pub vertex_inputs : Vec<VertexInput>
+}
+
+impl PipelineInterface {
+    /// Merges the stages, failing when two of them declare different resources at the same binding.
+    pub fn merge(stages : &[ShaderReflection]) -> Result<PipelineInterface, String> {
+        let mut interface = PipelineInterface {
+            descriptor_bindings : Vec::new(),
+            push_constant_ranges : Vec::new(),
+            vertex_inputs : Vec::new()
+        };
+
+        for stage in stages.iter() {
+            for binding in stage.descriptor_bindings.iter() {
+                let existing = interface.descriptor_bindings.iter_mut()
+                    .find(|existing| existing.set == binding.set && existing.binding == binding.binding);
+                match existing {
+                    Some(existing) if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count => {
+                        return Err(format!(
+                            "set {} binding {} is {} x {:?} in {:?} but {} x {:?} in {:?}",
+                            binding.set, binding.binding,
+                            existing.count, existing.descriptor_type, existing.stage_flags,
+                            binding.count, binding.descriptor_type, binding.stage_flags
+                        ));
+                    },
+                    Some(existing) => existing.stage_flags |= binding.stage_flags,
+                    None => interface.descriptor_bindings.push(binding.clone())
+                }
+            }
+            interface.push_constant_ranges.extend(stage.push_constant_range);
+            interface.vertex_inputs.extend(stage.vertex_inputs.iter().cloned());
+        }
+
+        interface.descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        Ok(interface)
+    }
+
+    /// Whether a pipeline with `other` interface can use the layouts created for this one. Names and vertex
+    /// inputs are ignored.
+    pub fn layout_matches(&self, other : &PipelineInterface) -> bool {
+        let same_binding = |a : &DescriptorBinding, b : &DescriptorBinding| {
+            (a.set, a.binding, a.descriptor_type, a.count, a.stage_flags) == (b.set, b.binding, b.descriptor_type, b.count, b.stage_flags)
+        };
+        let same_range = |a : &vk::PushConstantRange, b : &vk::PushConstantRange| {
+            (a.stage_flags, a.offset, a.size) == (b.stage_flags, b.offset, b.size)
+        };
+
+        self.descriptor_bindings.len() == other.descriptor_bindings.len()
+            && self.descriptor_bindings.iter().zip(other.descriptor_bindings.iter()).all(|(a, b)| same_binding(a, b))
+            && self.push_constant_ranges.len() == other.push_constant_ranges.len()
+            && self.push_constant_ranges.iter().zip(other.push_constant_ranges.iter()).all(|(a, b)| same_range(a, b))
+    }
+
+    /// Every stage that declares a push constant block, which is what `vkCmdPushConstants` has to be given
+    /// when all blocks start at offset 0.
+    pub fn push_constant_stages(&self) -> vk::ShaderStageFlags {
+        self.push_constant_ranges.iter().fold(vk::ShaderStageFlags::empty(), |stages, range| stages | range.stage_flags)
+    }
+
+    /// Number of descriptor sets, counting sets without bindings below the highest one used.
+    pub fn set_count(&self) -> u32 {
+        self.descriptor_bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0)
+    }
+
+    /// Creates one layout per set. On failure the layouts created so far are destroyed again.
+    pub fn create_set_layouts(&self, device : &ash::Device) -> Result<Vec<vk::DescriptorSetLayout>, RendererError> {
+        let mut set_layouts = Vec::new();
+        for set in 0..self.set_count() {
+            let bindings : Vec<vk::DescriptorSetLayoutBinding> = self.descriptor_bindings.iter()
+                .filter(|binding| binding.set == set)
+                .map(|binding| vk::DescriptorSetLayoutBinding {
+                    binding : binding.binding,
+                    descriptor_type : binding.descriptor_type,
+                    descriptor_count : binding.count,
+                    stage_flags : binding.stage_flags,
+                    p_immutable_samplers : std::ptr::null()
+                })
+                .collect();
+
+            let layout_info = vk::DescriptorSetLayoutCreateInfo {
+                s_type : vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+                p_next : std::ptr::null(),
+                binding_count : bindings.len() as u32,
+                p_bindings : bindings.as_ptr(),
+                flags : vk::DescriptorSetLayoutCreateFlags::empty()
+            };
+
+            match unsafe{ device.create_descriptor_set_layout(&layout_info, None) } {
+                Ok(set_layout) => set_layouts.push(set_layout),
+                Err(result) => {
+                    for set_layout in set_layouts {
+                        unsafe{ device.destroy_descriptor_set_layout(set_layout, None); }
+                    }
+                    return Err(result).context("failed to create descriptor set layout!");
+                }
+            }
+        }
+        Ok(set_layouts)
+    }
+
+    pub fn create_pipeline_layout(
+        &self,
+        device : &ash::Device,
+        set_layouts : &[vk::DescriptorSetLayout]
+    ) -> Result<vk::PipelineLayout, RendererError> {
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            s_type : vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            p_next : std::ptr::null(),
+            set_layout_count : set_layouts.len() as u32,
+            p_set_layouts : set_layouts.as_ptr(),
+            push_constant_range_count : self.push_constant_ranges.len() as u32,
+            p_push_constant_ranges : self.push_constant_ranges.as_ptr(),
+            flags : vk::PipelineLayoutCreateFlags::empty()
+        };
+
+        unsafe{
+            device.create_pipeline_layout(&pipeline_layout_info, None)
+            .context("failed to create pipeline layout")
+        }
+    }
+
+    /// Picks the attributes the vertex shader reads out of everything the vertex type `provided` offers.
+    /// Attributes the shader ignores are left out; a shader input without a matching attribute is an error.
+    pub fn vertex_attributes(
+        &self,
+        vertex_type : &str,
+        provided : &[vk::VertexInputAttributeDescription]
+    ) -> Result<Vec<vk::VertexInputAttributeDescription>, String> {
+        self.vertex_inputs.iter().map(|input| {
+            match provided.iter().find(|attribute| attribute.location == input.location) {
+                Some(attribute) if attribute.format == input.format => Ok(*attribute),
+                Some(attribute) => Err(format!(
+                    "vertex input {} at location {} is {:?}, but {} provides {:?}",
+                    input.name, input.location, input.format, vertex_type, attribute.format
+                )),
+                None => Err(format!(
+                    "vertex input {} at location {} ({:?}) has no matching attribute in {}",
+                    input.name, input.location, input.format, vertex_type
+                ))
+            }
+        }).collect()
+    }
+}
+
+/// Decodes a zero terminated string packed into words, as OpName stores it.
+fn parse_string(words : &[u32]) -> String {
+    let bytes : Vec<u8> = words.iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(code : &mut Vec<u32>, opcode : u32, operands : &[u32]) {
+        code.push(((operands.len() as u32 + 1) << 16) | opcode);
+        code.extend_from_slice(operands);
+    }
+
+    /// Packs `text` the way OpName and OpEntryPoint expect it, zero terminated and padded to whole words.
+    fn string(text : &str) -> Vec<u32> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(text.len() / 4 * 4 + 4, 0);
+        bytes.chunks(4).map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])).collect()
+    }
+
+    /// Hand assembled equivalent of
+    ///
+    /// ```glsl
+    /// layout(binding = 0) uniform UniformBufferObject { mat4 view; mat4 proj; } ubo;
+    /// layout(binding = 1) uniform texture2D texImage;
+    /// layout(binding = 2) uniform sampler texSampler;
+    /// layout(push_constant) uniform PushConstants { mat4 model; } pc;
+    /// ```
+    fn resource_module(execution_model : u32) -> Vec<u32> {
+        let mut code = vec![SPIRV_MAGIC, 0x00010000, 0, 17, 0];
+
+        let mut entry_point = vec![execution_model, 1];
+        entry_point.extend(string("main"));
+        instruction(&mut code, OP_ENTRY_POINT, &entry_point);
+        for (id, name) in [(7, "ubo"), (10, "texImage"), (13, "texSampler"), (16, "pc")] {
+            let mut operands = vec![id];
+            operands.extend(string(name));
+            instruction(&mut code, OP_NAME, &operands);
+        }
+
+        instruction(&mut code, OP_DECORATE, &[5, DECORATION_BLOCK]);
+        for member in 0..2 {
+            instruction(&mut code, OP_MEMBER_DECORATE, &[5, member, DECORATION_OFFSET, member * 64]);
+            instruction(&mut code, OP_MEMBER_DECORATE, &[5, member, DECORATION_MATRIX_STRIDE, 16]);
+        }
+        instruction(&mut code, OP_DECORATE, &[14, DECORATION_BLOCK]);
+        instruction(&mut code, OP_MEMBER_DECORATE, &[14, 0, DECORATION_OFFSET, 0]);
+        instruction(&mut code, OP_MEMBER_DECORATE, &[14, 0, DECORATION_MATRIX_STRIDE, 16]);
+        for (variable, binding) in [(7, 0), (10, 1), (13, 2)] {
+            instruction(&mut code, OP_DECORATE, &[variable, DECORATION_DESCRIPTOR_SET, 0]);
+            instruction(&mut code, OP_DECORATE, &[variable, DECORATION_BINDING, binding]);
+        }
+
+        instruction(&mut code, OP_TYPE_FLOAT, &[2, 32]);
+        instruction(&mut code, OP_TYPE_VECTOR, &[3, 2, 4]);
+        instruction(&mut code, OP_TYPE_MATRIX, &[4, 3, 4]);
+        instruction(&mut code, OP_TYPE_STRUCT, &[5, 4, 4]);
+        instruction(&mut code, OP_TYPE_POINTER, &[6, STORAGE_CLASS_UNIFORM, 5]);
+        instruction(&mut code, OP_VARIABLE, &[6, 7, STORAGE_CLASS_UNIFORM]);
+        // 2D, not depth, not arrayed, single sampled, used with a sampler, unknown format
+        instruction(&mut code, OP_TYPE_IMAGE, &[8, 2, 1, 0, 0, 0, 1, 0]);
+        instruction(&mut code, OP_TYPE_POINTER, &[9, STORAGE_CLASS_UNIFORM_CONSTANT, 8]);
+        instruction(&mut code, OP_VARIABLE, &[9, 10, STORAGE_CLASS_UNIFORM_CONSTANT]);
+        instruction(&mut code, OP_TYPE_SAMPLER, &[11]);
+        instruction(&mut code, OP_TYPE_POINTER, &[12, STORAGE_CLASS_UNIFORM_CONSTANT, 11]);
+        instruction(&mut code, OP_VARIABLE, &[12, 13, STORAGE_CLASS_UNIFORM_CONSTANT]);
+        instruction(&mut code, OP_TYPE_STRUCT, &[14, 4]);
+        instruction(&mut code, OP_TYPE_POINTER, &[15, STORAGE_CLASS_PUSH_CONSTANT, 14]);
+        instruction(&mut code, OP_VARIABLE, &[15, 16, STORAGE_CLASS_PUSH_CONSTANT]);
+        code
+    }
+
+    /// Hand assembled equivalent of
+    ///
+    /// ```glsl
+    /// layout(location = 0) in vec3 inPosition;
+    /// layout(location = 1) in vec2 inTexCoord;
+    /// ```
+    ///
+    /// with `gl_VertexIndex` read as well, which must not count as a vertex input.
+    fn vertex_input_module() -> Vec<u32> {
+        let mut code = vec![SPIRV_MAGIC, 0x00010000, 0, 12, 0];
+
+        let mut entry_point = vec![EXECUTION_MODEL_VERTEX, 1];
+        entry_point.extend(string("main"));
+        entry_point.extend([6, 8, 11]);
+        instruction(&mut code, OP_ENTRY_POINT, &entry_point);
+        for (id, name) in [(6, "inPosition"), (8, "inTexCoord"), (11, "gl_VertexIndex")] {
+            let mut operands = vec![id];
+            operands.extend(string(name));
+            instruction(&mut code, OP_NAME, &operands);
+        }
+
+        instruction(&mut code, OP_DECORATE, &[6, DECORATION_LOCATION, 0]);
+        instruction(&mut code, OP_DECORATE, &[8, DECORATION_LOCATION, 1]);
+        // BuiltIn VertexIndex
+        instruction(&mut code, OP_DECORATE, &[11, DECORATION_BUILT_IN, 42]);
+
+        instruction(&mut code, OP_TYPE_FLOAT, &[2, 32]);
+        instruction(&mut code, OP_TYPE_VECTOR, &[3, 2, 3]);
+        instruction(&mut code, OP_TYPE_VECTOR, &[4, 2, 2]);
+        instruction(&mut code, OP_TYPE_POINTER, &[5, STORAGE_CLASS_INPUT, 3]);
+        instruction(&mut code, OP_VARIABLE, &[5, 6, STORAGE_CLASS_INPUT]);
+        instruction(&mut code, OP_TYPE_POINTER, &[7, STORAGE_CLASS_INPUT, 4]);
+        instruction(&mut code, OP_VARIABLE, &[7, 8, STORAGE_CLASS_INPUT]);
+        instruction(&mut code, OP_TYPE_INT, &[9, 32, 1]);
+        instruction(&mut code, OP_TYPE_POINTER, &[10, STORAGE_CLASS_INPUT, 9]);
+        instruction(&mut code, OP_VARIABLE, &[10, 11, STORAGE_CLASS_INPUT]);
+        code
+    }
+
+    fn vertex_interface() -> PipelineInterface {
+        PipelineInterface::merge(&[ShaderReflection::reflect(&vertex_input_module()).unwrap()]).unwrap()
+    }
+
+    fn attribute(location : u32, format : vk::Format, offset : u32) -> vk::VertexInputAttributeDescription {
+        vk::VertexInputAttributeDescription { location, binding : 0, format, offset }
+    }
+
+    fn binding(binding : u32, descriptor_type : vk::DescriptorType, stage_flags : vk::ShaderStageFlags, name : &str) -> DescriptorBinding {
+        DescriptorBinding { set : 0, binding, descriptor_type, count : 1, stage_flags, name : name.to_owned() }
+    }
+
+    #[test]
+    fn reflects_descriptor_bindings_and_push_constants() {
+        let reflection = ShaderReflection::reflect(&resource_module(EXECUTION_MODEL_FRAGMENT)).unwrap();
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+
+        assert_eq!(reflection.stage, fragment);
+        assert_eq!(reflection.descriptor_bindings, vec![
+            binding(0, vk::DescriptorType::UNIFORM_BUFFER, fragment, "ubo"),
+            binding(1, vk::DescriptorType::SAMPLED_IMAGE, fragment, "texImage"),
+            binding(2, vk::DescriptorType::SAMPLER, fragment, "texSampler")
+        ]);
+
+        let range = reflection.push_constant_range.unwrap();
+        assert_eq!((range.stage_flags, range.offset, range.size), (fragment, 0, 64));
+        assert!(reflection.vertex_inputs.is_empty());
+    }
+
+    #[test]
+    fn merge_combines_the_stages_of_shared_bindings() {
+        let stages = [
+            ShaderReflection::reflect(&resource_module(EXECUTION_MODEL_VERTEX)).unwrap(),
+            ShaderReflection::reflect(&resource_module(EXECUTION_MODEL_FRAGMENT)).unwrap()
+        ];
+        let interface = PipelineInterface::merge(&stages).unwrap();
+        let both = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+
+        assert_eq!(interface.descriptor_bindings, vec![
+            binding(0, vk::DescriptorType::UNIFORM_BUFFER, both, "ubo"),
+            binding(1, vk::DescriptorType::SAMPLED_IMAGE, both, "texImage"),
+            binding(2, vk::DescriptorType::SAMPLER, both, "texSampler")
+        ]);
+        assert_eq!(interface.push_constant_ranges.len(), 2);
+        assert_eq!(interface.push_constant_stages(), both);
+        assert_eq!(interface.set_count(), 1);
+    }
+
+    #[test]
+    fn reflects_vertex_inputs_without_built_ins() {
+        let reflection = ShaderReflection::reflect(&vertex_input_module()).unwrap();
+
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.vertex_inputs, vec![
+            VertexInput { location : 0, format : vk::Format::R32G32B32_SFLOAT, name : "inPosition".to_owned() },
+            VertexInput { location : 1, format : vk::Format::R32G32_SFLOAT, name : "inTexCoord".to_owned() }
+        ]);
+        assert!(reflection.descriptor_bindings.is_empty());
+        assert!(reflection.push_constant_range.is_none());
+    }
+
+    #[test]
+    fn vertex_attributes_keep_only_what_the_shader_reads() {
+        let provided = [
+            attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32_SFLOAT, 12),
+            attribute(2, vk::Format::R32G32B32_SFLOAT, 20)
+        ];
+        let attributes = vertex_interface().vertex_attributes("Vertex", &provided).unwrap();
+
+        let attributes : Vec<(u32, vk::Format, u32)> = attributes.iter()
+            .map(|attribute| (attribute.location, attribute.format, attribute.offset))
+            .collect();
+        assert_eq!(attributes, vec![(0, vk::Format::R32G32B32_SFLOAT, 0), (1, vk::Format::R32G32_SFLOAT, 12)]);
+    }
+
+    #[test]
+    fn vertex_attributes_reject_a_format_mismatch() {
+        let provided = [
+            attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32B32_SFLOAT, 12)
+        ];
+        let error = vertex_interface().vertex_attributes("Vertex", &provided).unwrap_err();
+        assert!(error.contains("inTexCoord at location 1"), "{}", error);
+        assert!(error.contains("Vertex provides R32G32B32_SFLOAT"), "{}", error);
+    }
+
+    #[test]
+    fn vertex_attributes_reject_a_missing_location() {
+        let provided = [attribute(0, vk::Format::R32G32B32_SFLOAT, 0)];
+        let error = vertex_interface().vertex_attributes("Vertex", &provided).unwrap_err();
+        assert!(error.contains("inTexCoord at location 1"), "{}", error);
+        assert!(error.contains("no matching attribute in Vertex"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_code_that_is_not_spirv() {
+        assert!(ShaderReflection::reflect(&[0x12345678, 0, 0, 0, 0]).is_err());
+    }
+}
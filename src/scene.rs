@@ -0,0 +1,386 @@
+use cgmath::SquareMatrix;
+use crate::Vertex;
+use crate::error::RendererError;
+
+/// A run of triangles drawn with a single material.
+pub struct Primitive {
+    pub vertices : Vec<Vertex>,
+    pub indices : Vec<u32>,
+    pub material : usize
+}
+
+pub struct Mesh {
+    pub primitives : Vec<Primitive>
+}
+
+pub struct Material {
+    /// Index into `Scene::images`; the renderer substitutes a white texture when there is none.
+    pub base_color_texture : Option<usize>,
+    /// Blended over the opaque geometry instead of overwriting it.
+    pub alpha_blend : bool
+}
+
+pub struct Node {
+    pub local_transform : cgmath::Matrix4<f32>,
+    pub mesh : Option<usize>,
+    pub children : Vec<usize>
+}
+
+/// CPU side copy of everything the renderer needs to upload and draw.
+pub struct Scene {
+    pub nodes : Vec<Node>,
+    pub roots : Vec<usize>,
+    pub meshes : Vec<Mesh>,
+    pub materials : Vec<Material>,
+    pub images : Vec<image::RgbaImage>
+}
+
+impl Scene {
+    /// Wraps loose geometry in a scene with a single untransformed node and one material.
+    pub fn from_geometry(vertices : Vec<Vertex>, indices : Vec<u32>, texture : Option<image::RgbaImage>) -> Scene {
+        Scene {
+            nodes : vec![Node {
+                local_transform : cgmath::Matrix4::identity(),
+                mesh : Some(0),
+                children : Vec::new()
+            }],
+            roots : vec![0],
+            meshes : vec![Mesh {
+                primitives : vec![Primitive {
+                    vertices,
+                    indices,
+                    material : 0
+                }]
+            }],
+            materials : vec![Material {
+                base_color_texture : texture.as_ref().map(|_| 0),
+                alpha_blend : false
+            }],
+            images : texture.into_iter().collect()
+        }
+    }
+
+    /// Loads a triangulated `.obj` file as a single node with one primitive per object, picking up the
+    /// diffuse textures referenced by its `.mtl` file. Identical vertices are merged.
+    pub fn load_obj(path : &std::path::Path) -> Result<Scene, RendererError> {
+        let model_load_error = |message : String| RendererError::ModelLoad {
+            path : path.to_owned(),
+            message
+        };
+
+        let load_options = tobj::LoadOptions {
+            single_index : true,
+            triangulate : true,
+            ..Default::default()
+        };
+        let (models, obj_materials) = tobj::load_obj(path, &load_options)
+            .map_err(|error| model_load_error(error.to_string()))?;
+
+        // a missing or broken .mtl only costs us the textures, the geometry is still usable
+        let obj_materials = obj_materials.unwrap_or_else(|error| {
+            log::warn!("failed to load materials for {}: {}", path.display(), error);
+            Vec::new()
+        });
+
+        let directory = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+        let mut images = Vec::new();
+        let mut materials = Vec::new();
+        for obj_material in obj_materials.iter() {
+            let base_color_texture = match obj_material.diffuse_texture.is_empty() {
+                true => None,
+                false => {
+                    images.push(load_image(&directory.join(&obj_material.diffuse_texture))?);
+                    Some(images.len() - 1)
+                }
+            };
+            materials.push(Material {
+                base_color_texture,
+                // `dissolve` has no way into the shader, so blending could only ever draw at full opacity
+                alpha_blend : false
+            });
+        }
+        // faces without a material fall back to an untextured one
+        let default_material = materials.len();
+        materials.push(Material { base_color_texture : None, alpha_blend : false });
+
+        let mut primitives = Vec::new();
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let mut vertices : Vec<Vertex> = Vec::new();
+            let mut indices : Vec<u32> = Vec::new();
+            let mut unique_vertices : std::collections::HashMap<Vertex, u32> = std::collections::HashMap::new();
+
+            for &index in mesh.indices.iter() {
+                let index = index as usize;
+                let vertex = Vertex {
+                    pos : [
+                        mesh.positions[3 * index],
+                        mesh.positions[3 * index + 1],
+                        mesh.positions[3 * index + 2]
+                    ],
+                    color : match mesh.vertex_color.is_empty() {
+                        true => [1.0, 1.0, 1.0],
+                        false => [
+                            mesh.vertex_color[3 * index],
+                            mesh.vertex_color[3 * index + 1],
+                            mesh.vertex_color[3 * index + 2]
+                        ]
+                    },
+                    // OBJ puts the texture origin in the bottom left corner, Vulkan in the top left
+                    tex_coord : match mesh.texcoords.is_empty() {
+                        true => [0.0, 0.0],
+                        false => [mesh.texcoords[2 * index], 1.0 - mesh.texcoords[2 * index + 1]]
+                    },
+                    normal : match mesh.normals.is_empty() {
+                        true => [0.0, 0.0, 1.0],
+                        false => [
+                            mesh.normals[3 * index],
+                            mesh.normals[3 * index + 1],
+                            mesh.normals[3 * index + 2]
+                        ]
+                    }
+                };
+
+                let unique_index = *unique_vertices.entry(vertex).or_insert_with(|| {
+                    vertices.push(vertex);
+                    (vertices.len() - 1) as u32
+                });
+                indices.push(unique_index);
+            }
+
+            if indices.is_empty() {
+                continue;
+            }
+
+            log::info!("loaded object {} with {} vertices and {} indices", model.name, vertices.len(), indices.len());
+            primitives.push(Primitive {
+                vertices,
+                indices,
+                material : mesh.material_id.filter(|&id| id < default_material).unwrap_or(default_material)
+            });
+        }
+
+        if primitives.is_empty() {
+            return Err(model_load_error("the model does not contain any faces".to_owned()));
+        }
+
+        Ok(Scene {
+            nodes : vec![Node {
+                local_transform : cgmath::Matrix4::identity(),
+                mesh : Some(0),
+                children : Vec::new()
+            }],
+            roots : vec![0],
+            meshes : vec![Mesh { primitives }],
+            materials,
+            images
+        })
+    }
+
+    /// Imports the default scene of a `.gltf` or `.glb` file, keeping its node hierarchy.
+    /// Base color factors are folded into the vertex colors and only base color textures are loaded. Vertex
+    /// colors have no alpha, so blended materials get the factor's alpha baked into a copy of their texture.
+    pub fn load_gltf(path : &std::path::Path) -> Result<Scene, RendererError> {
+        let model_load_error = |message : String| RendererError::ModelLoad {
+            path : path.to_owned(),
+            message
+        };
+
+        let (document, buffers, gltf_images) = gltf::import(path)
+            .map_err(|error| model_load_error(error.to_string()))?;
+
+        let gltf_scene = document.default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| model_load_error("the file does not contain a scene".to_owned()))?;
+
+        let mut images = Vec::new();
+        let mut image_indices = std::collections::HashMap::new();
+        let mut materials = Vec::new();
+        let mut base_color_factors = Vec::new();
+        for gltf_material in document.materials() {
+            let pbr = gltf_material.pbr_metallic_roughness();
+            let base_color_texture = match pbr.base_color_texture() {
+                None => None,
+                Some(info) => {
+                    let source = info.texture().source().index();
+                    match image_indices.entry(source) {
+                        std::collections::hash_map::Entry::Occupied(entry) => Some(*entry.get()),
+                        std::collections::hash_map::Entry::Vacant(entry) => {
+                            images.push(convert_gltf_image(&gltf_images[source]).map_err(model_load_error)?);
+                            Some(*entry.insert(images.len() - 1))
+                        }
+                    }
+                }
+            };
+            let alpha_blend = gltf_material.alpha_mode() == gltf::material::AlphaMode::Blend;
+            let factor = pbr.base_color_factor();
+            let base_color_texture = match alpha_blend && factor[3] < 1.0 {
+                true => {
+                    let mut image = match base_color_texture {
+                        Some(index) => images[index].clone(),
+                        None => image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]))
+                    };
+                    for pixel in image.pixels_mut() {
+                        pixel[3] = (pixel[3] as f32 * factor[3]).round() as u8;
+                    }
+                    images.push(image);
+                    Some(images.len() - 1)
+                },
+                false => base_color_texture
+            };
+
+            materials.push(Material { base_color_texture, alpha_blend });
+            base_color_factors.push(factor);
+        }
+        // primitives without a material use the glTF default material, which is plain white
+        let default_material = materials.len();
+        materials.push(Material { base_color_texture : None, alpha_blend : false });
+        base_color_factors.push([1.0, 1.0, 1.0, 1.0]);
+
+        let mut meshes = Vec::new();
+        for gltf_mesh in document.meshes() {
+            let mut primitives = Vec::new();
+            for gltf_primitive in gltf_mesh.primitives() {
+                if gltf_primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!("skipping {:?} primitive in mesh {}", gltf_primitive.mode(), gltf_mesh.index());
+                    continue;
+                }
+
+                let reader = gltf_primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions : Vec<[f32; 3]> = match reader.read_positions() {
+                    Some(positions) => positions.collect(),
+                    None => {
+                        log::warn!("skipping primitive without positions in mesh {}", gltf_mesh.index());
+                        continue;
+                    }
+                };
+                let normals : Vec<[f32; 3]> = reader.read_normals()
+                    .map(|normals| normals.collect())
+                    .unwrap_or_else(|| vec![[0.0, 0.0, 1.0]; positions.len()]);
+                let tex_coords : Vec<[f32; 2]> = reader.read_tex_coords(0)
+                    .map(|tex_coords| tex_coords.into_f32().collect())
+                    .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
+                let colors : Vec<[f32; 3]> = reader.read_colors(0)
+                    .map(|colors| colors.into_rgb_f32().collect())
+                    .unwrap_or_else(|| vec![[1.0, 1.0, 1.0]; positions.len()]);
+                let indices : Vec<u32> = reader.read_indices()
+                    .map(|indices| indices.into_u32().collect())
+                    .unwrap_or_else(|| (0..positions.len() as u32).collect());
+
+                let material = gltf_primitive.material().index().unwrap_or(default_material);
+                let factor = base_color_factors[material];
+
+                let vertices = (0..positions.len()).map(|idx| Vertex {
+                    pos : positions[idx],
+                    color : [colors[idx][0] * factor[0], colors[idx][1] * factor[1], colors[idx][2] * factor[2]],
+                    tex_coord : tex_coords[idx],
+                    normal : normals[idx]
+                }).collect();
+
+                primitives.push(Primitive {
+                    vertices,
+                    indices,
+                    material
+                });
+            }
+            meshes.push(Mesh { primitives });
+        }
+
+        let mut nodes : Vec<Node> = document.nodes().map(|gltf_node| Node {
+            local_transform : cgmath::Matrix4::from(gltf_node.transform().matrix()),
+            mesh : gltf_node.mesh().map(|mesh| mesh.index()),
+            children : gltf_node.children().map(|child| child.index()).collect()
+        }).collect();
+
+        // glTF is Y up while the renderer looks at a Z up world, so the scene hangs off a rotated root
+        nodes.push(Node {
+            local_transform : cgmath::Matrix4::from_angle_x(cgmath::Deg(90.0)),
+            mesh : None,
+            children : gltf_scene.nodes().map(|node| node.index()).collect()
+        });
+
+        Ok(Scene {
+            roots : vec![nodes.len() - 1],
+            nodes,
+            meshes,
+            materials,
+            images
+        })
+    }
+
+    /// Makes every material sample `texture` instead of its own base color texture.
+    pub fn override_texture(&mut self, texture : image::RgbaImage) {
+        self.images = vec![texture];
+        for material in self.materials.iter_mut() {
+            material.base_color_texture = Some(0);
+        }
+    }
+
+    /// Model matrices of every node, indexed like `nodes`.
+    pub fn world_transforms(&self) -> Vec<cgmath::Matrix4<f32>> {
+        let mut world_transforms = vec![cgmath::Matrix4::identity(); self.nodes.len()];
+        let mut stack : Vec<(usize, cgmath::Matrix4<f32>)> = self.roots.iter()
+            .map(|&root| (root, cgmath::Matrix4::identity()))
+            .collect();
+
+        while let Some((node, parent_transform)) = stack.pop() {
+            let world_transform = parent_transform * self.nodes[node].local_transform;
+            world_transforms[node] = world_transform;
+            for &child in self.nodes[node].children.iter() {
+                stack.push((child, world_transform));
+            }
+        }
+        world_transforms
+    }
+}
+
+pub fn load_image(path : &std::path::Path) -> Result<image::RgbaImage, RendererError> {
+    image::open(path)
+        .map(|image| image.to_rgba8())
+        .map_err(|error| RendererError::ImageLoad {
+            path : path.to_owned(),
+            message : error.to_string()
+        })
+}
+
+/// Expands any of the pixel layouts gltf can decode to RGBA8.
+fn convert_gltf_image(data : &gltf::image::Data) -> Result<image::RgbaImage, String> {
+    use gltf::image::Format;
+
+    let (channels, bytes_per_channel, bgr) = match data.format {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::B8G8R8 => (3, 1, true),
+        Format::B8G8R8A8 => (4, 1, true),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false)
+    };
+
+    let channel = |pixel : &[u8], idx : usize| -> u8 {
+        match bytes_per_channel {
+            1 => pixel[idx],
+            _ => (u16::from_ne_bytes([pixel[idx * 2], pixel[idx * 2 + 1]]) >> 8) as u8
+        }
+    };
+
+    let mut rgba = Vec::with_capacity((data.width * data.height * 4) as usize);
+    for pixel in data.pixels.chunks_exact(channels * bytes_per_channel) {
+        let texel = match channels {
+            1 => [channel(pixel, 0), channel(pixel, 0), channel(pixel, 0), 255],
+            2 => [channel(pixel, 0), channel(pixel, 0), channel(pixel, 0), channel(pixel, 1)],
+            3 => [channel(pixel, 0), channel(pixel, 1), channel(pixel, 2), 255],
+            _ => [channel(pixel, 0), channel(pixel, 1), channel(pixel, 2), channel(pixel, 3)]
+        };
+        match bgr {
+            true => rgba.extend_from_slice(&[texel[2], texel[1], texel[0], texel[3]]),
+            false => rgba.extend_from_slice(&texel)
+        }
+    }
+
+    image::RgbaImage::from_raw(data.width, data.height, rgba)
+        .ok_or_else(|| format!("image data does not match its {}x{} size", data.width, data.height))
+}
@@ -0,0 +1,127 @@
+use ash::vk;
+use crate::error::RendererError;
+
+/// Part of every cache key, so that SPIR-V written by an older compiler or with other options is not reused.
+/// `NAGA_VERSION` is the version Cargo.lock resolved, see build.rs.
+const COMPILER_ID : &str = concat!("naga-", env!("NAGA_VERSION"), " spv-1.0");
+
+/// Compiles a GLSL or WGSL shader to SPIR-V, reusing the result of an earlier compilation of the same source.
+///
+/// GLSL is picked for every extension but `.wgsl`. The entry point has to be called `main` in either language;
+/// WGSL files therefore hold a single stage. Compiled code is kept in the per-user cache directory under a hash
+/// of the source, which only costs a file read on the next start.
+pub fn compile(path : &std::path::Path, stage : vk::ShaderStageFlags) -> Result<Vec<u32>, RendererError> {
+    let source = std::fs::read_to_string(path).map_err(|error| RendererError::ShaderLoad {
+        path : path.to_owned(),
+        source : error
+    })?;
+
+    let naga_stage = match stage {
+        vk::ShaderStageFlags::VERTEX => naga::ShaderStage::Vertex,
+        vk::ShaderStageFlags::FRAGMENT => naga::ShaderStage::Fragment,
+        vk::ShaderStageFlags::COMPUTE => naga::ShaderStage::Compute,
+        _ => return Err(RendererError::InvalidArgument(format!("cannot compile {:?} shader {}", stage, path.display())))
+    };
+
+    let cache_path = crate::cache_directory().map(|directory| {
+        let key = fnv1a_64(&[COMPILER_ID.as_bytes(), format!("{:?}", naga_stage).as_bytes(), source.as_bytes()]);
+        directory.join("shaders").join(format!("{:016x}.spv", key))
+    });
+
+    if let Some(code) = cache_path.as_ref().and_then(|cache_path| read_cached(cache_path)) {
+        log::debug!("using cached SPIR-V for {}", path.display());
+        return Ok(code);
+    }
+
+    let code = compile_source(path, &source, naga_stage)?;
+    log::info!("compiled {}", path.display());
+
+    if let Some(cache_path) = cache_path.as_ref() {
+        let bytes : Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let temporary_path = cache_path.with_extension("tmp");
+        let result = cache_path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&temporary_path, &bytes))
+            .and_then(|_| std::fs::rename(&temporary_path, cache_path));
+        if let Err(error) = result {
+            log::warn!("failed to cache SPIR-V in {}: {}", cache_path.display(), error);
+        }
+    }
+    Ok(code)
+}
+
+fn compile_source(path : &std::path::Path, source : &str, stage : naga::ShaderStage) -> Result<Vec<u32>, RendererError> {
+    let compile_error = |location : Option<naga::SourceLocation>, message : String| RendererError::ShaderCompile {
+        path : path.to_owned(),
+        line : location.map(|location| location.line_number),
+        message
+    };
+
+    let module = match path.extension().is_some_and(|extension| extension == "wgsl") {
+        true => naga::front::wgsl::parse_str(source)
+            .map_err(|error| compile_error(error.location(source), error.message().to_owned()))?,
+        false => naga::front::glsl::Frontend::default()
+            .parse(&naga::front::glsl::Options::from(stage), source)
+            .map_err(|errors| {
+                // later errors are frequently caused by the first one
+                let error = &errors.errors[0];
+                compile_error(error.location(source), error.kind.to_string())
+            })?
+    };
+
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|error| compile_error(error.location(source), error_chain(error.as_inner())))?;
+
+    let mut options = naga::back::spv::Options::default();
+    // the renderer already works in Vulkan clip space, see `camera::OPENGL_TO_VULKAN_MATRIX`
+    options.flags.remove(naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    options.lang_version = (1, 0);
+
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage : stage,
+        entry_point : "main".to_owned()
+    };
+    naga::back::spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+        .map_err(|error| compile_error(None, error.to_string()))
+}
+
+/// Returns the cached code if the file exists and looks like SPIR-V.
+fn read_cached(cache_path : &std::path::Path) -> Option<Vec<u32>> {
+    let bytes = std::fs::read(cache_path).ok()?;
+    match ash::util::read_spv(&mut std::io::Cursor::new(bytes)) {
+        Ok(code) => Some(code),
+        Err(error) => {
+            log::warn!("ignoring broken cached SPIR-V {}: {}", cache_path.display(), error);
+            None
+        }
+    }
+}
+
+/// Validation errors nest the actual problem a few levels deep, e.g. "entry point main is invalid" only says
+/// where to look.
+fn error_chain(error : &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same result in every build, as cache keys on disk need.
+fn fnv1a_64(parts : &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for part in parts {
+        for &byte in part.iter() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        // keeps ("ab", "c") and ("a", "bc") apart
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the shader directory is scanned. Editors and compilers write files in several steps, so
+/// scanning every frame would mostly observe half written files.
+const POLL_INTERVAL : Duration = Duration::from_millis(500);
+
+/// Detects changed shaders by polling the modification times of the files in a directory.
+pub struct ShaderWatcher {
+    directory : PathBuf,
+    extensions : &'static [&'static str],
+    modified : HashMap<PathBuf, SystemTime>,
+    last_poll : Instant
+}
+
+impl ShaderWatcher {
+    /// Starts watching files with one of `extensions` in `directory`. Files that already exist only count as
+    /// changed once they are modified again.
+    pub fn new(directory : &Path, extensions : &'static [&'static str]) -> ShaderWatcher {
+        let mut watcher = ShaderWatcher {
+            directory : directory.to_owned(),
+            extensions,
+            modified : HashMap::new(),
+            last_poll : Instant::now()
+        };
+        watcher.modified = watcher.scan();
+        watcher
+    }
+
+    /// Returns the files that were created or modified since the previous poll, at most once per
+    /// `POLL_INTERVAL`. Deleted files are forgotten, so restoring one counts as a change.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let modified = self.scan();
+        let mut changed : Vec<PathBuf> = modified.iter()
+            .filter(|(path, time)| self.modified.get(*path) != Some(*time))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.sort();
+        self.modified = modified;
+        changed
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                log::debug!("failed to scan {}: {}", self.directory.display(), error);
+                return HashMap::new();
+            }
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let extension = path.extension().and_then(|extension| extension.to_str());
+                extension.is_some_and(|extension| self.extensions.contains(&extension))
+            })
+            .filter_map(|path| {
+                let time = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+                Some((path, time))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,254 @@
+use ash::vk;
+use crate::allocator::{align_up, Allocation, Allocator, ResourceKind};
+use crate::error::{RendererError, VkResultExt};
+
+/// Staging blocks are at least this large so that small uploads share one buffer.
+const STAGING_BLOCK_SIZE : vk::DeviceSize = 4 * 1024 * 1024;
+
+/// Offsets into a staging block satisfy the copy alignment of every format the renderer uploads.
+const STAGING_ALIGNMENT : vk::DeviceSize = 16;
+
+/// A persistently mapped host visible buffer that is filled front to back between two flushes.
+struct StagingBlock {
+    buffer : vk::Buffer,
+    allocation : Allocation,
+    mapped : *mut u8,
+    size : vk::DeviceSize,
+    used : vk::DeviceSize
+}
+
+/// Records transfer commands into a single command buffer and submits them together on `flush`.
+///
+/// Data passed to `stage` is copied into recycled staging blocks which stay alive until the submission
+/// has completed, so callers never have to keep their own staging buffers around.
+pub struct UploadContext {
+    device : ash::Device,
+    queue : vk::Queue,
+    command_pool : vk::CommandPool,
+    command_buffer : vk::CommandBuffer,
+    fence : vk::Fence,
+    recording : bool,
+    staging_blocks : Vec<StagingBlock>
+}
+
+impl UploadContext {
+    pub fn new(
+        device : &ash::Device,
+        queue : vk::Queue,
+        queue_family_index : u32
+    ) -> Result<UploadContext, RendererError> {
+        let pool_info = vk::CommandPoolCreateInfo {
+            s_type : vk::StructureType::COMMAND_POOL_CREATE_INFO,
+            p_next : std::ptr::null(),
+            queue_family_index,
+            flags : vk::CommandPoolCreateFlags::TRANSIENT
+        };
+
+        let command_pool = unsafe{
+            device.create_command_pool(&pool_info, None)
+            .context("failed to create upload command pool!")?
+        };
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            s_type : vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            p_next : std::ptr::null(),
+            level : vk::CommandBufferLevel::PRIMARY,
+            command_pool,
+            command_buffer_count : 1
+        };
+
+        let fence_info = vk::FenceCreateInfo {
+            s_type : vk::StructureType::FENCE_CREATE_INFO,
+            p_next : std::ptr::null(),
+            flags : vk::FenceCreateFlags::empty()
+        };
+
+        let handles = unsafe{
+            device.allocate_command_buffers(&alloc_info)
+            .context("failed to allocate upload command buffer!")
+            .and_then(|command_buffers| {
+                device.create_fence(&fence_info, None)
+                .context("failed to create upload fence!")
+                .map(|fence| (command_buffers[0], fence))
+            })
+        };
+
+        match handles {
+            Ok((command_buffer, fence)) => Ok(UploadContext {
+                device : device.clone(),
+                queue,
+                command_pool,
+                command_buffer,
+                fence,
+                recording : false,
+                staging_blocks : Vec::new()
+            }),
+            Err(error) => {
+                unsafe{ device.destroy_command_pool(command_pool, None); }
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the command buffer of the current batch, beginning a new batch if necessary.
+    pub fn command_buffer(&mut self) -> Result<vk::CommandBuffer, RendererError> {
+        if !self.recording {
+            let begin_info = vk::CommandBufferBeginInfo {
+                s_type : vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+                p_next : std::ptr::null(),
+                flags : vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                p_inheritance_info : std::ptr::null()
+            };
+
+            unsafe{
+                self.device.begin_command_buffer(self.command_buffer, &begin_info)
+                .context("failed to begin recording upload command buffer")?;
+            }
+            self.recording = true;
+        }
+        Ok(self.command_buffer)
+    }
+
+    /// Copies `data` into staging memory and returns the buffer and offset it can be transferred from.
+    /// The range stays valid until the next `flush` completes.
+    pub fn stage(&mut self, allocator : &Allocator, data : &[u8]) -> Result<(vk::Buffer, vk::DeviceSize), RendererError> {
+        let size = data.len() as vk::DeviceSize;
+
+        let fits = |block : &StagingBlock| align_up(block.used, STAGING_ALIGNMENT) + size <= block.size;
+        let block_index = match self.staging_blocks.iter().position(fits) {
+            Some(block_index) => block_index,
+            None => {
+                let block = self.create_staging_block(allocator, std::cmp::max(size, STAGING_BLOCK_SIZE))?;
+                self.staging_blocks.push(block);
+                self.staging_blocks.len() - 1
+            }
+        };
+
+        let block = &mut self.staging_blocks[block_index];
+        let offset = align_up(block.used, STAGING_ALIGNMENT);
+        unsafe{
+            block.mapped.add(offset as usize).copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+        block.used = offset + size;
+        Ok((block.buffer, offset))
+    }
+
+    /// Submits everything recorded since the last flush and waits on the fence. Afterwards at most one
+    /// `STAGING_BLOCK_SIZE` block is kept for the next batch, the others are returned to `allocator`.
+    pub fn flush(&mut self, allocator : &Allocator) -> Result<(), RendererError> {
+        if !self.recording {
+            return Ok(());
+        }
+        self.recording = false;
+
+        let command_buffers = [self.command_buffer];
+        let submit_info = [vk::SubmitInfo {
+            s_type : vk::StructureType::SUBMIT_INFO,
+            p_next : std::ptr::null(),
+            command_buffer_count : 1,
+            p_command_buffers : command_buffers.as_ptr(),
+            wait_semaphore_count : 0,
+            p_wait_semaphores : std::ptr::null(),
+            signal_semaphore_count : 0,
+            p_signal_semaphores : std::ptr::null(),
+            p_wait_dst_stage_mask : std::ptr::null()
+        }];
+
+        let result = unsafe{
+            self.device.end_command_buffer(self.command_buffer)
+            .context("failed to record upload command buffer")
+            .and_then(|_| {
+                self.device.queue_submit(self.queue, &submit_info, self.fence)
+                .context("failed to submit upload command buffer")
+            })
+            .and_then(|_| {
+                self.device.wait_for_fences(&[self.fence], true, u64::MAX)
+                .context("failed to wait for upload fence")
+            })
+        };
+
+        // a failed submission leaves nothing pending, so the pool and blocks can be recycled either way
+        unsafe{
+            self.device.reset_fences(&[self.fence]).context("failed to reset upload fence")?;
+            self.device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())
+            .context("failed to reset upload command pool")?;
+        }
+
+        // a large initial upload must not keep its staging memory for the lifetime of the renderer
+        let mut kept = false;
+        for mut block in std::mem::take(&mut self.staging_blocks) {
+            if !kept && block.size == STAGING_BLOCK_SIZE {
+                kept = true;
+                block.used = 0;
+                self.staging_blocks.push(block);
+            } else {
+                self.destroy_staging_block(allocator, block);
+            }
+        }
+        result
+    }
+
+    /// Destroys the staging blocks and command pool. Must not be called while a submission is pending,
+    /// which `flush` guarantees by waiting.
+    pub fn destroy(&mut self, allocator : &Allocator) {
+        for block in std::mem::take(&mut self.staging_blocks) {
+            self.destroy_staging_block(allocator, block);
+        }
+        unsafe{
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+
+    fn destroy_staging_block(&self, allocator : &Allocator, block : StagingBlock) {
+        unsafe{ self.device.destroy_buffer(block.buffer, None); }
+        allocator.free(block.allocation);
+    }
+
+    fn create_staging_block(&self, allocator : &Allocator, size : vk::DeviceSize) -> Result<StagingBlock, RendererError> {
+        let buffer_info = vk::BufferCreateInfo {
+            s_type : vk::StructureType::BUFFER_CREATE_INFO,
+            p_next : std::ptr::null(),
+            size,
+            usage : vk::BufferUsageFlags::TRANSFER_SRC,
+            sharing_mode : vk::SharingMode::EXCLUSIVE,
+            flags : vk::BufferCreateFlags::empty(),
+            queue_family_index_count : 0,
+            p_queue_family_indices : std::ptr::null()
+        };
+
+        let buffer = unsafe{
+            self.device.create_buffer(&buffer_info, None)
+            .context("failed to create staging buffer!")?
+        };
+
+        let mem_requirements = unsafe{ self.device.get_buffer_memory_requirements(buffer) };
+        let allocation = allocator.allocate(
+            &mem_requirements,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ResourceKind::Linear
+        ).and_then(|allocation| {
+            match unsafe{ self.device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) } {
+                Ok(()) => Ok(allocation),
+                Err(result) => {
+                    allocator.free(allocation);
+                    Err(result).context("failed to bind staging buffer memory!")
+                }
+            }
+        });
+
+        match allocation {
+            Ok(allocation) => Ok(StagingBlock {
+                buffer,
+                mapped : allocation.mapped_ptr().unwrap(),
+                allocation,
+                size,
+                used : 0
+            }),
+            Err(error) => {
+                unsafe{ self.device.destroy_buffer(buffer, None); }
+                Err(error)
+            }
+        }
+    }
+}